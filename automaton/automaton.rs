@@ -0,0 +1,467 @@
+// A cellular automaton engine shared by the days which simulate one (day11,
+// day17 and day24). Each day includes this file as a module, and uses only
+// some of it.
+#![allow(dead_code)]
+
+use std::hash::{BuildHasherDefault, Hash, Hasher};
+
+// The default SipHash is slow for the small integer cells of an automaton,
+// and there's no need to resist collision attacks here. This is the
+// multiply-rotate hash that rustc uses internally (FxHash).
+#[derive(Default)]
+pub struct CellHasher(u64);
+impl Hasher for CellHasher {
+  fn write(&mut self, bytes: &[u8]) {
+    for chunk in bytes.chunks(8) {
+      let mut word = [0u8; 8];
+      word[..chunk.len()].copy_from_slice(chunk);
+      self.write_u64(u64::from_le_bytes(word));
+    }
+  }
+
+  fn write_u64(&mut self, n: u64) {
+    self.0 = (self.0.rotate_left(5) ^ n).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+  }
+
+  fn write_usize(&mut self, n: usize) {
+    self.write_u64(n as u64);
+  }
+
+  fn write_i64(&mut self, n: i64) {
+    self.write_u64(n as u64);
+  }
+
+  fn write_isize(&mut self, n: isize) {
+    self.write_u64(n as u64);
+  }
+
+  fn finish(&self) -> u64 {
+    self.0
+  }
+}
+
+pub type HashSet<T> = std::collections::HashSet<T, BuildHasherDefault<CellHasher>>;
+pub type HashMap<K, V> = std::collections::HashMap<K, V, BuildHasherDefault<CellHasher>>;
+
+// The shape of an unbounded space that cells live in: which cells neighbour
+// each other.
+pub trait Topology {
+  type Cell: Copy + Eq + Hash;
+
+  // Calls `f` with each neighbour of `cell`.
+  fn for_each_neighbour<F: FnMut(Self::Cell)>(&self, cell: Self::Cell, f: F);
+}
+
+// Which numbers of live neighbours make a dead cell come alive, and which
+// keep a live cell alive.
+#[derive(Clone, Debug)]
+pub struct Rules {
+  birth: Vec<bool>,
+  survival: Vec<bool>,
+}
+impl Rules {
+  pub fn new<B, S>(birth: B, survival: S) -> Self
+  where
+    B: IntoIterator<Item = usize>,
+    S: IntoIterator<Item = usize>,
+  {
+    fn to_table<I: IntoIterator<Item = usize>>(counts: I) -> Vec<bool> {
+      let mut table = Vec::new();
+      for count in counts {
+        if count >= table.len() {
+          table.resize(count + 1, false);
+        }
+        table[count] = true;
+      }
+      table
+    }
+    Rules {
+      birth: to_table(birth),
+      survival: to_table(survival),
+    }
+  }
+
+  pub fn next_state(&self, alive: bool, live_neighbours: usize) -> bool {
+    let table = if alive { &self.survival } else { &self.birth };
+    *table.get(live_neighbours).unwrap_or(&false)
+  }
+
+  fn births_from_nothing(&self) -> bool {
+    self.next_state(false, 0)
+  }
+}
+
+// Runs generations of a cellular automaton in an unbounded space, keeping
+// only the set of live cells. Bounded grids use GridAutomaton instead.
+//
+// A cell can only change if it or one of its neighbours changed in the
+// previous generation, so after the first generation only those cells are
+// looked at.
+pub struct Automaton<T: Topology> {
+  topology: T,
+  rules: Rules,
+  live: HashSet<T::Cell>,
+  generation: usize,
+  // The cells that may change in the next generation, or None before the
+  // first generation.
+  dirty: Option<Vec<T::Cell>>,
+}
+impl<T: Topology> Automaton<T> {
+  pub fn new<I: IntoIterator<Item = T::Cell>>(topology: T, rules: Rules, live: I) -> Self {
+    assert!(
+      !rules.births_from_nothing(),
+      "cells can only be born with no live neighbours in a bounded grid"
+    );
+    Automaton {
+      topology,
+      rules,
+      live: live.into_iter().collect(),
+      generation: 0,
+      dirty: None,
+    }
+  }
+
+  pub fn topology(&self) -> &T {
+    &self.topology
+  }
+
+  pub fn generation(&self) -> usize {
+    self.generation
+  }
+
+  pub fn is_alive(&self, cell: T::Cell) -> bool {
+    self.live.contains(&cell)
+  }
+
+  pub fn live_cells(&self) -> impl Iterator<Item = &T::Cell> {
+    self.live.iter()
+  }
+
+  pub fn live_count(&self) -> usize {
+    self.live.len()
+  }
+
+  pub fn live_neighbours(&self, cell: T::Cell) -> usize {
+    let mut count = 0;
+    self.topology.for_each_neighbour(cell, |n| count += self.live.contains(&n) as usize);
+    count
+  }
+
+  // The cells to look at in the next generation.
+  fn candidates(&mut self) -> Vec<T::Cell> {
+    if let Some(dirty) = self.dirty.take() {
+      return dirty;
+    }
+    // Without births from nothing, only live cells and their neighbours can
+    // be alive in the next generation.
+    let mut candidates = self.live.clone();
+    for &cell in &self.live {
+      self.topology.for_each_neighbour(cell, |n| {
+        candidates.insert(n);
+      });
+    }
+    candidates.into_iter().collect()
+  }
+
+  // Runs a single generation, returning the cells that changed.
+  pub fn step(&mut self) -> Vec<T::Cell> {
+    let changed = self
+      .candidates()
+      .into_iter()
+      .filter(|&cell| {
+        let alive = self.live.contains(&cell);
+        self.rules.next_state(alive, self.live_neighbours(cell)) != alive
+      })
+      .collect::<Vec<_>>();
+
+    let mut dirty: HashSet<T::Cell> = HashSet::default();
+    for &cell in &changed {
+      if !self.live.remove(&cell) {
+        self.live.insert(cell);
+      }
+      dirty.insert(cell);
+      self.topology.for_each_neighbour(cell, |n| {
+        dirty.insert(n);
+      });
+    }
+    self.dirty = Some(dirty.into_iter().collect());
+    self.generation += 1;
+    changed
+  }
+
+  pub fn run(&mut self, generations: usize) {
+    for _ in 0..generations {
+      self.step();
+    }
+  }
+
+  // Runs generations until one changes nothing. Returns the number of
+  // generations which changed something.
+  pub fn run_until_stable(&mut self) -> usize {
+    let start = self.generation;
+    while !self.step().is_empty() {}
+    self.generation - start - 1
+  }
+}
+
+// A rectangular grid where only some positions hold cells, such as seats
+// among floor. Cells are the indices of their positions, in reading order.
+// Neighbours are precomputed, as either the adjacent cells or the first cell
+// seen in each of the 8 directions.
+pub struct SquareGrid {
+  width: usize,
+  height: usize,
+  cells: Vec<usize>,
+  // The neighbours of the cell at position `p` are
+  // `neighbours[offsets[p]..offsets[p + 1]]`.
+  offsets: Vec<usize>,
+  neighbours: Vec<usize>,
+}
+impl SquareGrid {
+  // Cells that are next to each other, including diagonally.
+  pub fn adjacent(width: usize, height: usize, is_cell: &[bool]) -> Self {
+    Self::build(width, height, is_cell, true)
+  }
+
+  // The first cell visible in each of the 8 directions, looking past any
+  // positions without a cell.
+  pub fn line_of_sight(width: usize, height: usize, is_cell: &[bool]) -> Self {
+    Self::build(width, height, is_cell, false)
+  }
+
+  fn build(width: usize, height: usize, is_cell: &[bool], adjacent_only: bool) -> Self {
+    assert_eq!(is_cell.len(), width * height);
+    let cells = (0..is_cell.len()).filter(|&p| is_cell[p]).collect::<Vec<_>>();
+
+    // Seeing is symmetric, so walk each line across the grid in 4 of the
+    // directions and link each cell to the previous cell on the line.
+    let mut lists: Vec<Vec<usize>> = vec![Vec::new(); is_cell.len()];
+    let (w, h) = (width as i64, height as i64);
+    for &(dx, dy) in &[(1i64, 0i64), (0, 1), (1, 1), (1, -1)] {
+      // Every line starts on a position whose previous position is off the
+      // grid.
+      for start_y in 0..h {
+        for start_x in 0..w {
+          if (0..w).contains(&(start_x - dx)) && (0..h).contains(&(start_y - dy)) {
+            continue;
+          }
+          let (mut x, mut y) = (start_x, start_y);
+          // The last cell on the line, and how many steps back it was.
+          let mut last: Option<(usize, usize)> = None;
+          while (0..w).contains(&x) && (0..h).contains(&y) {
+            let p = (x + y * w) as usize;
+            last = last.map(|(c, dist)| (c, dist + 1));
+            if is_cell[p] {
+              if let Some((prev, dist)) = last {
+                if !adjacent_only || dist == 1 {
+                  lists[p].push(prev);
+                  lists[prev].push(p);
+                }
+              }
+              last = Some((p, 0));
+            }
+            x += dx;
+            y += dy;
+          }
+        }
+      }
+    }
+
+    let mut offsets = Vec::with_capacity(is_cell.len() + 1);
+    let mut neighbours = Vec::new();
+    offsets.push(0);
+    for list in lists {
+      neighbours.extend(list);
+      offsets.push(neighbours.len());
+    }
+    SquareGrid {
+      width,
+      height,
+      cells,
+      offsets,
+      neighbours,
+    }
+  }
+
+  pub fn width(&self) -> usize {
+    self.width
+  }
+
+  pub fn height(&self) -> usize {
+    self.height
+  }
+
+  pub fn neighbours_of(&self, p: usize) -> &[usize] {
+    &self.neighbours[self.offsets[p]..self.offsets[p + 1]]
+  }
+}
+
+// Runs generations of a cellular automaton on a SquareGrid, with the state
+// of every position kept in a flat Vec<bool>.
+//
+// As with Automaton, only cells that changed, or that neighbour a cell which
+// changed, are looked at after the first generation. The next generation is
+// written into a second buffer, which is first brought up to date by
+// replaying the previous generation's changes onto it, and then the buffers
+// are swapped.
+pub struct GridAutomaton {
+  grid: SquareGrid,
+  rules: Rules,
+  live: Vec<bool>,
+  next: Vec<bool>,
+  live_count: usize,
+  generation: usize,
+  // The cells that may change in the next generation.
+  dirty: Vec<usize>,
+  // The generation in which each position was last added to `dirty`.
+  dirty_in: Vec<usize>,
+  // The cells that changed in the last generation.
+  changed: Vec<usize>,
+}
+impl GridAutomaton {
+  pub fn new<I: IntoIterator<Item = usize>>(grid: SquareGrid, rules: Rules, live: I) -> Self {
+    let len = grid.width * grid.height;
+    let mut state = vec![false; len];
+    for cell in live {
+      state[cell] = true;
+    }
+    let live_count = state.iter().filter(|&&alive| alive).count();
+    GridAutomaton {
+      dirty: grid.cells.clone(),
+      grid,
+      rules,
+      next: state.clone(),
+      live: state,
+      live_count,
+      generation: 0,
+      dirty_in: vec![0; len],
+      changed: Vec::new(),
+    }
+  }
+
+  pub fn topology(&self) -> &SquareGrid {
+    &self.grid
+  }
+
+  pub fn generation(&self) -> usize {
+    self.generation
+  }
+
+  pub fn is_alive(&self, cell: usize) -> bool {
+    self.live[cell]
+  }
+
+  pub fn live_count(&self) -> usize {
+    self.live_count
+  }
+
+  // Runs a single generation, returning the cells that changed.
+  pub fn step(&mut self) -> Vec<usize> {
+    self.generation += 1;
+    for &cell in &self.changed {
+      self.next[cell] = self.live[cell];
+    }
+    self.changed.clear();
+
+    for &cell in &self.dirty {
+      let alive = self.live[cell];
+      let count = self.grid.neighbours_of(cell).iter().filter(|&&n| self.live[n]).count();
+      let next = self.rules.next_state(alive, count);
+      self.next[cell] = next;
+      if next != alive {
+        self.changed.push(cell);
+      }
+    }
+    std::mem::swap(&mut self.live, &mut self.next);
+
+    self.dirty.clear();
+    for &cell in &self.changed {
+      if self.live[cell] {
+        self.live_count += 1;
+      } else {
+        self.live_count -= 1;
+      }
+      for &n in std::iter::once(&cell).chain(self.grid.neighbours_of(cell)) {
+        if self.dirty_in[n] != self.generation {
+          self.dirty_in[n] = self.generation;
+          self.dirty.push(n);
+        }
+      }
+    }
+    self.changed.clone()
+  }
+
+  pub fn run(&mut self, generations: usize) {
+    for _ in 0..generations {
+      self.step();
+    }
+  }
+
+  // Runs generations until one changes nothing. Returns the number of
+  // generations which changed something.
+  pub fn run_until_stable(&mut self) -> usize {
+    let start = self.generation;
+    while !self.step().is_empty() {}
+    self.generation - start - 1
+  }
+}
+
+// An unbounded N-dimensional lattice, where every cell within one step along
+// any combination of axes is a neighbour.
+pub struct Lattice<const N: usize> {
+  offsets: Vec<[i64; N]>,
+}
+impl<const N: usize> Lattice<N> {
+  pub fn new() -> Self {
+    let mut offsets = vec![[0; N]];
+    for axis in 0..N {
+      offsets = offsets
+        .into_iter()
+        .flat_map(|offset| {
+          (-1..=1).map(move |d| {
+            let mut offset = offset;
+            offset[axis] = d;
+            offset
+          })
+        })
+        .collect();
+    }
+    offsets.retain(|offset| offset.iter().any(|&d| d != 0));
+    Lattice { offsets }
+  }
+}
+impl<const N: usize> Default for Lattice<N> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+impl<const N: usize> Topology for Lattice<N> {
+  type Cell = [i64; N];
+
+  fn for_each_neighbour<F: FnMut([i64; N])>(&self, cell: [i64; N], mut f: F) {
+    for offset in &self.offsets {
+      let mut n = cell;
+      for axis in 0..N {
+        n[axis] += offset[axis];
+      }
+      f(n);
+    }
+  }
+}
+
+// An unbounded grid of hexagons with points at the top and bottom. Cells are
+// (x, y) with rows offset by half a hexagon, so x is even on even rows and
+// odd on odd rows, and east and west neighbours are 2 apart.
+pub struct HexGrid;
+impl HexGrid {
+  pub const DIRECTIONS: [(isize, isize); 6] = [(-2, 0), (-1, 1), (1, 1), (2, 0), (1, -1), (-1, -1)];
+}
+impl Topology for HexGrid {
+  type Cell = (isize, isize);
+
+  fn for_each_neighbour<F: FnMut((isize, isize))>(&self, (x, y): (isize, isize), mut f: F) {
+    for (dx, dy) in &Self::DIRECTIONS {
+      f((x + dx, y + dy));
+    }
+  }
+}
@@ -1,48 +1,556 @@
+#[macro_use]
+extern crate anyhow;
+use std::collections::BTreeMap;
+
 static INPUT_FILE: &str = "day10/input.txt";
 
-fn main() {
-  let input_all: String = std::fs::read_to_string(INPUT_FILE).unwrap();
-  let mut nums_all : Vec<u32> = input_all.split_terminator("\n").map(|x| x.parse().unwrap()).collect();
-  nums_all.sort();
+// An unsigned integer without an upper bound, for counting arrangements which
+// grow exponentially with the number of adapters. Stored as base 10^9 digits,
+// least significant first, so that it's easy to print.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct BigCount(Vec<u32>);
+impl BigCount {
+  const BASE: u64 = 1_000_000_000;
+
+  fn zero() -> Self {
+    BigCount(Vec::new())
+  }
+
+  fn from_u64(mut n: u64) -> Self {
+    let mut digits = Vec::new();
+    while n > 0 {
+      digits.push((n % Self::BASE) as u32);
+      n /= Self::BASE;
+    }
+    BigCount(digits)
+  }
+
+  fn is_zero(&self) -> bool {
+    self.0.is_empty()
+  }
+
+  // The value, if it fits in a u64.
+  #[cfg(test)]
+  fn to_u64(&self) -> Option<u64> {
+    self.0.iter().rev().try_fold(0u64, |n, &digit| n.checked_mul(Self::BASE)?.checked_add(digit as u64))
+  }
+
+  fn sub_assign(&mut self, other: &BigCount) {
+    assert!(*self >= *other);
+    let mut borrow = 0;
+    for i in 0..self.0.len() {
+      let sub = *other.0.get(i).unwrap_or(&0) as i64 + borrow;
+      let mut digit = self.0[i] as i64 - sub;
+      borrow = 0;
+      if digit < 0 {
+        digit += Self::BASE as i64;
+        borrow = 1;
+      }
+      self.0[i] = digit as u32;
+    }
+    while self.0.last() == Some(&0) {
+      self.0.pop();
+    }
+  }
+
+  fn add_assign(&mut self, other: &BigCount) {
+    let mut carry = 0;
+    for i in 0..std::cmp::max(self.0.len(), other.0.len()) {
+      let sum = *self.0.get(i).unwrap_or(&0) as u64 + *other.0.get(i).unwrap_or(&0) as u64 + carry;
+      if i < self.0.len() {
+        self.0[i] = (sum % Self::BASE) as u32;
+      } else {
+        self.0.push((sum % Self::BASE) as u32);
+      }
+      carry = sum / Self::BASE;
+    }
+    if carry > 0 {
+      self.0.push(carry as u32);
+    }
+  }
+}
+impl std::cmp::Ord for BigCount {
+  fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+    // Digits are never stored with leading zeros.
+    self.0.len().cmp(&other.0.len()).then_with(|| self.0.iter().rev().cmp(other.0.iter().rev()))
+  }
+}
+impl std::cmp::PartialOrd for BigCount {
+  fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+    Some(self.cmp(other))
+  }
+}
+impl std::str::FromStr for BigCount {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> anyhow::Result<Self> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+      return Err(anyhow!("invalid number `{}`", s));
+    }
+    let mut digits = s
+      .as_bytes()
+      .rchunks(9)
+      .map(|chunk| std::str::from_utf8(chunk).unwrap().parse().unwrap())
+      .collect::<Vec<u32>>();
+    while digits.last() == Some(&0) {
+      digits.pop();
+    }
+    Ok(BigCount(digits))
+  }
+}
+impl std::fmt::Display for BigCount {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match self.0.split_last() {
+      None => write!(f, "0"),
+      Some((most, rest)) => {
+        write!(f, "{}", most)?;
+        for digit in rest.iter().rev() {
+          write!(f, "{:09}", digit)?;
+        }
+        Ok(())
+      }
+    }
+  }
+}
+
+// A small seedable random number generator (SplitMix64), so that sampled
+// arrangements can be reproduced.
+struct Rng(u64);
+impl Rng {
+  fn next_u64(&mut self) -> u64 {
+    self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = self.0;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+  }
+
+  // A number in 0..n, for n up to BigCount::BASE, where the bias from the
+  // modulo is negligible.
+  fn below(&mut self, n: u64) -> u64 {
+    self.next_u64() % n
+  }
+
+  // A uniformly random number in 0..n, by picking random digits up to the
+  // size of `n` and rejecting any that are too large.
+  fn below_big(&mut self, n: &BigCount) -> BigCount {
+    assert!(!n.is_zero());
+    let top = *n.0.last().unwrap() as u64;
+    loop {
+      let mut digits = (0..n.0.len() - 1).map(|_| self.below(BigCount::BASE) as u32).collect::<Vec<_>>();
+      digits.push(self.below(top + 1) as u32);
+      while digits.last() == Some(&0) {
+        digits.pop();
+      }
+      let candidate = BigCount(digits);
+      if candidate < *n {
+        return candidate;
+      }
+    }
+  }
+}
+
+// How adapters may be connected together.
+#[derive(Clone, Copy, Debug)]
+struct JoltRules {
+  // The smallest and largest joltage increase between connected adapters.
+  min_step: u32,
+  max_step: u32,
+  // How much higher the device's joltage is than the highest adapter.
+  device_offset: u32,
+}
+impl JoltRules {
+  // The rules from the puzzle.
+  const PUZZLE: JoltRules = JoltRules {
+    min_step: 1,
+    max_step: 3,
+    device_offset: 3,
+  };
+}
+
+struct AdapterChain {
+  rules: JoltRules,
+  // The sorted joltages from the outlet (always 0) through each adapter, up to
+  // the device.
+  jolts: Vec<u32>,
+  // The DP table from `paths_to_end`, which every query about arrangements
+  // uses.
+  paths: Vec<BigCount>,
+}
+impl AdapterChain {
+  fn new(adapters: &[u32], rules: JoltRules) -> anyhow::Result<Self> {
+    if rules.min_step > rules.max_step {
+      return Err(anyhow!("min step {} is above max step {}", rules.min_step, rules.max_step));
+    }
+    let mut jolts = Vec::with_capacity(adapters.len() + 2);
+    jolts.push(0);
+    jolts.extend_from_slice(adapters);
+    jolts.sort_unstable();
+    let highest = *jolts.last().unwrap();
+    let device = highest
+      .checked_add(rules.device_offset)
+      .ok_or_else(|| anyhow!("device joltage {} + {} is too large", highest, rules.device_offset))?;
+    jolts.push(device);
+    let mut chain = AdapterChain {
+      rules,
+      jolts,
+      paths: Vec::new(),
+    };
+    chain.paths = chain.paths_to_end();
+    Ok(chain)
+  }
+
+  fn is_valid_step(&self, from: u32, to: u32) -> bool {
+    to >= from && to - from >= self.rules.min_step && to - from <= self.rules.max_step
+  }
+
+  // Counts each joltage difference in the chain that uses every adapter, from
+  // the outlet to the device. Fails if every adapter can't be used.
+  fn difference_histogram(&self) -> anyhow::Result<BTreeMap<u32, usize>> {
+    let mut histogram = BTreeMap::new();
+    for pair in self.jolts.windows(2) {
+      if !self.is_valid_step(pair[0], pair[1]) {
+        return Err(anyhow!(
+          "no chain uses every adapter: can't step from {} to {} jolts with steps of {} to {}",
+          pair[0],
+          pair[1],
+          self.rules.min_step,
+          self.rules.max_step
+        ));
+      }
+      *histogram.entry(pair[1] - pair[0]).or_insert(0) += 1;
+    }
+    Ok(histogram)
+  }
+
+  // The indices in `jolts` that can be connected after the one at `i`, in
+  // increasing order of joltage.
+  fn next_steps<'a>(&'a self, i: usize) -> impl Iterator<Item = usize> + 'a {
+    let from = self.jolts[i];
+    (i + 1..self.jolts.len())
+      .take_while(move |&j| self.jolts[j] <= from.saturating_add(self.rules.max_step))
+      .filter(move |&j| self.is_valid_step(from, self.jolts[j]))
+  }
 
-  let mut current_jolts = 0;
-  let mut count_1_diffs = 0;
-  let mut count_3_diffs = 1;  // From the last adaptor to the device is always 3 jolts.
-  for n in &nums_all {
-    assert!(*n <= current_jolts + 3);
-    match n - current_jolts {
-      1 => count_1_diffs += 1,
-      3 => count_3_diffs += 1,
-      _ => (),
+  // For each joltage in `jolts`, the number of ways to connect it to the
+  // device.
+  fn paths_to_end(&self) -> Vec<BigCount> {
+    let mut paths = vec![BigCount::zero(); self.jolts.len()];
+    *paths.last_mut().unwrap() = BigCount::from_u64(1);
+    for i in (0..self.jolts.len() - 1).rev() {
+      let mut count = BigCount::zero();
+      for j in self.next_steps(i) {
+        count.add_assign(&paths[j]);
+      }
+      paths[i] = count;
     }
-    current_jolts = *n;
+    paths
+  }
+
+  fn indices_to_jolts(&self, indices: &[usize]) -> Vec<u32> {
+    indices.iter().map(|&i| self.jolts[i]).collect()
   }
-  println!("Part 1 {}", count_1_diffs * count_3_diffs);
 
-  struct PathStep {
-    paths_to_end : u64,
-    jolts : u32,
+  // Lazily produces every arrangement in lexicographic order. Each is the
+  // full chain of joltages, starting with the outlet and ending with the
+  // device.
+  fn arrangements(&self) -> Arrangements<'_> {
+    Arrangements {
+      chain: self,
+      path: Vec::new(),
+      started: false,
+    }
   }
-  impl PathStep {
-    fn new(jolts : u32) -> Self {
-      Self{paths_to_end: 0, jolts: jolts}
+
+  // The arrangement at (zero-based) position `k` in lexicographic order.
+  //
+  // The arrangements that begin by stepping to a given adapter are all
+  // before those which step to any higher adapter, and the DP table says how
+  // many of them there are. So at each step the choice is found by skipping
+  // over whole groups of arrangements.
+  fn nth_arrangement(&self, k: &BigCount) -> anyhow::Result<Vec<u32>> {
+    let paths = &self.paths;
+    if *k >= paths[0] {
+      return Err(anyhow!("there are only {} arrangements, can't get number {}", paths[0], k));
+    }
+    let mut k = k.clone();
+    let mut path = vec![0];
+    let mut i = 0;
+    while i != self.jolts.len() - 1 {
+      i = self
+        .next_steps(i)
+        .find(|&j| {
+          if k < paths[j] {
+            true
+          } else {
+            k.sub_assign(&paths[j]);
+            false
+          }
+        })
+        .unwrap();
+      path.push(i);
+    }
+    Ok(self.indices_to_jolts(&path))
+  }
+
+  // An arrangement picked uniformly at random, reproducibly for a given seed.
+  fn sample_arrangement(&self, seed: u64) -> anyhow::Result<Vec<u32>> {
+    let total = self.count_arrangements()?;
+    self.nth_arrangement(&Rng(seed).below_big(&total))
+  }
+
+  // The arrangement using the fewest adapters, and the earliest in
+  // lexicographic order among those.
+  fn shortest_arrangement(&self) -> anyhow::Result<Vec<u32>> {
+    let paths = &self.paths;
+    if paths[0].is_zero() {
+      return Err(anyhow!("no chain of adapters connects the outlet to the device"));
     }
-    fn new_with_path(jolts : u32) -> Self {
-      Self{paths_to_end: 1, jolts: jolts}
+    // The fewest steps to the device, for the joltages with a path to it.
+    let mut fewest = vec![usize::MAX; self.jolts.len()];
+    *fewest.last_mut().unwrap() = 0;
+    for i in (0..self.jolts.len() - 1).rev() {
+      if let Some(steps) = self.next_steps(i).filter(|&j| !paths[j].is_zero()).map(|j| fewest[j]).min() {
+        fewest[i] = steps + 1;
+      }
     }
+    let mut path = vec![0];
+    let mut i = 0;
+    while i != self.jolts.len() - 1 {
+      let want = fewest[i] - 1;
+      i = self.next_steps(i).find(|&j| fewest[j] == want).unwrap();
+      path.push(i);
+    }
+    Ok(self.indices_to_jolts(&path))
+  }
+
+  // The number of distinct ways to connect the outlet to the device. Fails if
+  // there are none.
+  fn count_arrangements(&self) -> anyhow::Result<BigCount> {
+    let count = &self.paths[0];
+    if count.is_zero() {
+      return Err(anyhow!(
+        "no chain of adapters connects the outlet to the device at {} jolts",
+        self.jolts.last().unwrap()
+      ));
+    }
+    Ok(count.clone())
   }
-  let mut jolt_set: Vec<PathStep> = nums_all.iter().map(|x| PathStep::new(*x)).collect();
-  jolt_set.insert(0, PathStep::new(0));
-  jolt_set.push(PathStep::new_with_path(jolt_set.last().unwrap().jolts + 3));
+}
 
-  for i in (0..jolt_set.len() - 1).rev() {
-    let current_jolts = jolt_set[i].jolts;
-    for j in i+1..=i+3 {
-      if j == jolt_set.len() { break; }
-      if jolt_set[j].jolts > current_jolts + 3 { break; }
+// An iterator over the arrangements of an AdapterChain, in lexicographic order.
+// It walks depth-first through the joltages, using the DP table to avoid
+// stepping into any joltage that has no path to the device.
+struct Arrangements<'a> {
+  chain: &'a AdapterChain,
+  // The indices in `chain.jolts` of the last arrangement produced.
+  path: Vec<usize>,
+  started: bool,
+}
+impl<'a> Arrangements<'a> {
+  // Extends `path` to the device, choosing the lowest joltage at each step.
+  fn descend(&mut self) {
+    let end = self.chain.jolts.len() - 1;
+    while *self.path.last().unwrap() != end {
+      let i = *self.path.last().unwrap();
+      let paths = &self.chain.paths;
+      let j = self.chain.next_steps(i).find(|&j| !paths[j].is_zero()).unwrap();
+      self.path.push(j);
+    }
+  }
+}
+impl<'a> Iterator for Arrangements<'a> {
+  type Item = Vec<u32>;
 
-      jolt_set[i].paths_to_end += jolt_set[j].paths_to_end;
+  fn next(&mut self) -> Option<Vec<u32>> {
+    if !self.started {
+      self.started = true;
+      if self.chain.paths[0].is_zero() {
+        return None;
+      }
+      self.path.push(0);
+      self.descend();
+      return Some(self.chain.indices_to_jolts(&self.path));
     }
+    // Backtrack to the last joltage which has a higher choice of next step
+    // than the one taken, take it, and then descend from there.
+    while self.path.len() > 1 {
+      let taken = self.path.pop().unwrap();
+      let i = *self.path.last().unwrap();
+      let paths = &self.chain.paths;
+      if let Some(j) = self.chain.next_steps(i).find(|&j| j > taken && !paths[j].is_zero()) {
+        self.path.push(j);
+        self.descend();
+        return Some(self.chain.indices_to_jolts(&self.path));
+      }
+    }
+    None
+  }
+}
+
+fn main() -> anyhow::Result<()> {
+  let input_all: String = std::fs::read_to_string(INPUT_FILE)?;
+  let nums_all: Vec<u32> = input_all
+    .split_terminator("\n")
+    .map(|x| x.parse())
+    .collect::<Result<_, _>>()?;
+  let chain = AdapterChain::new(&nums_all, JoltRules::PUZZLE)?;
+
+  let histogram = chain.difference_histogram()?;
+  let count_diffs = |d| *histogram.get(&d).unwrap_or(&0);
+  println!("Part 1 {}", count_diffs(1) * count_diffs(3));
+  println!("Part 2 {}", chain.count_arrangements()?);
+
+  // `day10 list N`, `day10 nth K`, `day10 sample SEED` and `day10 shortest`
+  // print arrangements of the input's adapters.
+  let args = std::env::args().skip(1).collect::<Vec<_>>();
+  let arg = || args.get(1).ok_or_else(|| anyhow!("{} needs an argument", args[0]));
+  let print_arrangement = |jolts: Vec<u32>| {
+    let jolts = jolts.iter().map(|j| j.to_string()).collect::<Vec<_>>();
+    println!("{}", jolts.join(" "));
+  };
+  match args.first().map(|s| s.as_str()) {
+    Some("list") => chain.arrangements().take(arg()?.parse()?).for_each(print_arrangement),
+    Some("nth") => print_arrangement(chain.nth_arrangement(&arg()?.parse()?)?),
+    Some("sample") => print_arrangement(chain.sample_arrangement(arg()?.parse()?)?),
+    Some("shortest") => print_arrangement(chain.shortest_arrangement()?),
+    _ => (),
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SMALL_INPUT: [u32; 11] = [16, 10, 15, 5, 1, 11, 7, 19, 6, 12, 4];
+  const LARGE_INPUT: [u32; 31] = [
+    28, 33, 18, 42, 31, 14, 46, 20, 48, 47, 24, 23, 49, 45, 19, 38, 39, 11, 1, 32, 25, 35, 8, 17, 7,
+    9, 4, 2, 34, 10, 3,
+  ];
+
+  #[test]
+  fn test_examples() -> anyhow::Result<()> {
+    let chain = AdapterChain::new(&SMALL_INPUT, JoltRules::PUZZLE)?;
+    let histogram = chain.difference_histogram()?;
+    assert_eq!(histogram.into_iter().collect::<Vec<_>>(), vec![(1, 7), (3, 5)]);
+    assert_eq!(chain.count_arrangements()?.to_u64(), Some(8));
+
+    let chain = AdapterChain::new(&LARGE_INPUT, JoltRules::PUZZLE)?;
+    let histogram = chain.difference_histogram()?;
+    assert_eq!(histogram.into_iter().collect::<Vec<_>>(), vec![(1, 22), (3, 10)]);
+    assert_eq!(chain.count_arrangements()?.to_u64(), Some(19208));
+    Ok(())
+  }
+
+  #[test]
+  fn test_rules() -> anyhow::Result<()> {
+    // A gap of 4 can't be bridged with the puzzle rules, but can with larger
+    // steps, which also allow skipping adapters.
+    let chain = AdapterChain::new(&[1, 5], JoltRules::PUZZLE)?;
+    assert!(chain.difference_histogram().is_err());
+    assert!(chain.count_arrangements().is_err());
+    let rules = JoltRules {
+      max_step: 4,
+      ..JoltRules::PUZZLE
+    };
+    let chain = AdapterChain::new(&[1, 4, 5], rules)?;
+    assert_eq!(chain.count_arrangements()?.to_u64(), Some(5));
+
+    // Steps of exactly 2, so the odd adapter can't be used.
+    let rules = JoltRules {
+      min_step: 2,
+      max_step: 2,
+      device_offset: 2,
+    };
+    let chain = AdapterChain::new(&[2, 3, 4], rules)?;
+    assert!(chain.difference_histogram().is_err());
+    assert_eq!(chain.count_arrangements()?.to_u64(), Some(1));
+
+    // Joltages near the top of the range.
+    assert!(AdapterChain::new(&[u32::MAX - 1], JoltRules::PUZZLE).is_err());
+    let rules = JoltRules {
+      max_step: u32::MAX,
+      ..JoltRules::PUZZLE
+    };
+    let chain = AdapterChain::new(&[1, u32::MAX - 3], rules)?;
+    assert_eq!(chain.count_arrangements()?.to_u64(), Some(4));
+    Ok(())
+  }
+
+  #[test]
+  fn test_arrangements() -> anyhow::Result<()> {
+    let chain = AdapterChain::new(&SMALL_INPUT, JoltRules::PUZZLE)?;
+    let all = chain.arrangements().collect::<Vec<_>>();
+    assert_eq!(all.len(), 8);
+    assert_eq!(all[0], vec![0, 1, 4, 5, 6, 7, 10, 11, 12, 15, 16, 19, 22]);
+    assert_eq!(all[7], vec![0, 1, 4, 7, 10, 12, 15, 16, 19, 22]);
+    let mut sorted = all.clone();
+    sorted.sort();
+    sorted.dedup();
+    assert_eq!(all, sorted);
+
+    for (k, arrangement) in all.iter().enumerate() {
+      assert_eq!(&chain.nth_arrangement(&BigCount::from_u64(k as u64))?, arrangement);
+    }
+    assert!(chain.nth_arrangement(&BigCount::from_u64(8)).is_err());
+
+    for seed in 0..20 {
+      let sample = chain.sample_arrangement(seed)?;
+      assert!(all.contains(&sample));
+      assert_eq!(chain.sample_arrangement(seed)?, sample);
+    }
+
+    assert_eq!(chain.shortest_arrangement()?, vec![0, 1, 4, 7, 10, 12, 15, 16, 19, 22]);
+
+    // Dead ends are skipped: with steps of 2 or 3 the adapter at 4 can't reach
+    // anything.
+    let rules = JoltRules {
+      min_step: 2,
+      max_step: 3,
+      device_offset: 3,
+    };
+    let chain = AdapterChain::new(&[1, 2, 4, 5], rules)?;
+    assert_eq!(chain.arrangements().collect::<Vec<_>>(), vec![vec![0, 2, 5, 8]]);
+    let chain = AdapterChain::new(&[1, 5], JoltRules::PUZZLE)?;
+    assert_eq!(chain.arrangements().next(), None);
+    assert!(chain.shortest_arrangement().is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_big_count() -> anyhow::Result<()> {
+    // Adapters at every joltage give tribonacci numbers of arrangements, which
+    // don't fit in a u64 for long.
+    let adapters = (1..=100).collect::<Vec<_>>();
+    let chain = AdapterChain::new(&adapters, JoltRules::PUZZLE)?;
+    let count = chain.count_arrangements()?;
+    assert_eq!(count.to_u64(), None);
+    assert_eq!(count.to_string(), "180396380815100901214157639");
+    assert_eq!(count, "180396380815100901214157639".parse()?);
+
+    // The last arrangement steps 3 at a time as much as it can.
+    let mut last = count.clone();
+    last.sub_assign(&BigCount::from_u64(1));
+    let mut expected = (0..=99).step_by(3).collect::<Vec<_>>();
+    expected.extend_from_slice(&[100, 103]);
+    assert_eq!(chain.nth_arrangement(&last)?, expected);
+    // The shortest one takes just as many steps, but starts with the smallest.
+    let mut shortest = vec![0];
+    shortest.extend((1..=100).step_by(3));
+    shortest.push(103);
+    assert_eq!(chain.shortest_arrangement()?, shortest);
+    assert_eq!(shortest.len(), expected.len());
+    assert!(chain.sample_arrangement(1)?.len() > expected.len());
+
+    let mut n = BigCount::from_u64(999_999_999_999_999_999);
+    n.add_assign(&BigCount::from_u64(1));
+    assert_eq!(n.to_string(), "1000000000000000000");
+    n.sub_assign(&BigCount::from_u64(1));
+    assert_eq!(n.to_u64(), Some(999_999_999_999_999_999));
+    assert_eq!(BigCount::zero().to_string(), "0");
+    assert_eq!("000".parse::<BigCount>()?, BigCount::zero());
+    assert!(BigCount::from_u64(1_000_000_000) > BigCount::from_u64(999_999_999));
+    Ok(())
   }
-  println!("Part 2 {}", jolt_set[0].paths_to_end);
-}
\ No newline at end of file
+}
@@ -1,3 +1,11 @@
+#[macro_use]
+extern crate anyhow;
+
+#[path = "../automaton/automaton.rs"]
+mod automaton;
+use automaton::{GridAutomaton, Rules, SquareGrid};
+use std::collections::HashSet;
+
 static INPUT_FILE : &str = "day11/input.txt";
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -6,39 +14,49 @@ enum Spot {
   Floor,
 }
 
-const W : usize = 99;
-const H : usize = 95;
-
 #[derive(Clone)]
 struct World {
-  spots: [Spot; W * H],
+  width: usize,
+  height: usize,
+  // Rows of `width` spots, from the top.
+  spots: Vec<Spot>,
 }
 impl World {
-  fn from_string(input: &str) -> Self {
-    let mut w = World {spots: [Spot::Floor; W * H]};
-    let mut input_iter = input.chars().filter(|&x| x == '#' || x == 'L' || x == '.');
-    for s in &mut w.spots {
-      *s = match input_iter.next().unwrap() as char {
-        '#' => Spot::Chair(true),
-        'L' => Spot::Chair(false),
-        '.' => Spot::Floor,
-        _  => panic!("Unexpected input character\n")
+  fn from_string(input: &str) -> anyhow::Result<Self> {
+    let mut w = World {width: 0, height: 0, spots: Vec::new()};
+    for (y, line) in input.lines().map(|line| line.trim()).filter(|line| !line.is_empty()).enumerate() {
+      for c in line.chars() {
+        w.spots.push(match c {
+          '#' => Spot::Chair(true),
+          'L' => Spot::Chair(false),
+          '.' => Spot::Floor,
+          _ => return Err(anyhow!("Unexpected input character `{}` on row {}", c, y)),
+        });
       }
+      let row_width = w.spots.len() - y * w.width;
+      if y == 0 {
+        w.width = row_width;
+      } else if row_width != w.width {
+        return Err(anyhow!("Row {} has {} spots but row 0 has {}", y, row_width, w.width));
+      }
+      w.height += 1;
+    }
+    if w.spots.is_empty() {
+      return Err(anyhow!("Empty input"));
     }
-    assert!(input_iter.next().is_none());
-    w
+    Ok(w)
   }
 
   fn get(&self, x: usize, y: usize) -> Spot {
-    assert!(x < W);
-    assert!(y < H);
-    self.spots[x + y * W]
+    assert!(x < self.width);
+    assert!(y < self.height);
+    self.spots[x + y * self.width]
   }
 
   fn set(&mut self, x: usize, y: usize, spot: Spot) {
-    assert!(x < W);
-    assert!(y < H);
-    self.spots[x + y * W] = spot;
+    assert!(x < self.width);
+    assert!(y < self.height);
+    self.spots[x + y * self.width] = spot;
   }
 
   fn is_empty_chair(&self, x: usize, y: usize) -> bool {
@@ -51,8 +69,8 @@ impl World {
 
   fn count_neighbours(&self, x: usize, y: usize) -> usize {
     let mut count = 0;
-    for xd in x.saturating_sub(1)..std::cmp::min(W, x+2) {
-      for yd in y.saturating_sub(1)..std::cmp::min(H, y+2) {
+    for xd in x.saturating_sub(1)..std::cmp::min(self.width, x+2) {
+      for yd in y.saturating_sub(1)..std::cmp::min(self.height, y+2) {
         if (xd != x || yd != y) && self.get(xd, yd) == Spot::Chair(true) {
           count += 1;
         }
@@ -71,7 +89,7 @@ impl World {
       }
     }
     // Down.
-    for yd in y+1..H {
+    for yd in y+1..self.height {
       if let Spot::Chair(full) = self.get(x, yd)  {
         count += full as usize;
         break;
@@ -85,7 +103,7 @@ impl World {
       }
     }
     // Right.
-    for xd in x+1..W {
+    for xd in x+1..self.width {
       if let Spot::Chair(full) = self.get(xd, y)  {
         count += full as usize;
         break;
@@ -99,21 +117,21 @@ impl World {
       }
     }
     // Up-Right.
-    for (xd, yd) in (x+1..W).zip((0..y).rev()) {
+    for (xd, yd) in (x+1..self.width).zip((0..y).rev()) {
       if let Spot::Chair(full) = self.get(xd, yd)  {
         count += full as usize;
         break;
       }
     }
     // Down-Left.
-    for (xd, yd) in (0..x).rev().zip(y+1..H) {
+    for (xd, yd) in (0..x).rev().zip(y+1..self.height) {
       if let Spot::Chair(full) = self.get(xd, yd)  {
         count += full as usize;
         break;
       }
     }
     // Down-Right.
-    for (xd, yd) in (x+1..W).zip(y+1..H) {
+    for (xd, yd) in (x+1..self.width).zip(y+1..self.height) {
       if let Spot::Chair(full) = self.get(xd, yd)  {
         count += full as usize;
         break;
@@ -125,36 +143,28 @@ impl World {
   fn count_full_chairs(&self) -> usize {
         self.spots.iter().filter(|&&x| x == Spot::Chair(true)).count()
   }
-
-  #[allow(dead_code)]
-  fn print(&self) {
-    for y in 0..H {
-      for x in 0..W {
-        let c = match self.get(x, y) {
-            Spot::Chair(true) => '#',
-            Spot::Chair(false) => 'L',
-            Spot::Floor => '.',
-        };
-        print!("{}", c);
-      }
-      println!("");
-    }
-    }
 }
 
-fn main() {
-  let file = std::fs::read_to_string(INPUT_FILE).unwrap();
-
+// Runs the seating rules until nobody moves, where `count` gives the
+// number of occupied seats that matter to a seat, and `tolerance` is how many
+// of them make someone leave. Returns the number of occupied seats at the end.
+//
+// This recounts every seat's neighbours on every generation, and is kept to
+// check `simulate()` against.
+fn run_until_stable(
+  mut world: World,
+  count: fn(&World, usize, usize) -> usize,
+  tolerance: usize,
+) -> usize {
   let mut changed = false;
-  let mut world = World::from_string(&file);
   loop {
     let mut next_world = world.clone();
-    for x in 0..W {
-      for y in 0..H {
-        if world.is_empty_chair(x, y) && world.count_neighbours(x, y) == 0 {
+    for x in 0..world.width {
+      for y in 0..world.height {
+        if world.is_empty_chair(x, y) && count(&world, x, y) == 0 {
           next_world.set(x, y, Spot::Chair(true));
           changed = true;
-        } else if world.is_full_chair(x, y) && world.count_neighbours(x, y) >= 4 {
+        } else if world.is_full_chair(x, y) && count(&world, x, y) >= tolerance {
           next_world.set(x, y, Spot::Chair(false));
           changed = true;
         }
@@ -165,27 +175,300 @@ fn main() {
     if !changed { break; }
     changed = false;
   }
-  println!("Part 1 {}", world.count_full_chairs());
+  world.count_full_chairs()
+}
 
-  world = World::from_string(&file);
-  changed = false;
-  loop {
-    let mut next_world = world.clone();
-    for x in 0..W {
-      for y in 0..H {
-        if world.is_empty_chair(x, y) && world.count_sight_neighbours(x, y) == 0 {
-          next_world.set(x, y, Spot::Chair(true));
-          changed = true;
-        } else if world.is_full_chair(x, y) && world.count_sight_neighbours(x, y) >= 5 {
-          next_world.set(x, y, Spot::Chair(false));
-          changed = true;
+impl World {
+  fn is_chair(&self) -> Vec<bool> {
+    self.spots.iter().map(|&spot| spot != Spot::Floor).collect()
+  }
+
+  fn full_chairs(&self) -> impl Iterator<Item = usize> + '_ {
+    (0..self.spots.len()).filter(move |&p| self.spots[p] == Spot::Chair(true))
+  }
+}
+
+// Someone sits in an empty seat if no seat that matters to it is occupied, and
+// leaves once `tolerance` of them are.
+fn seating_rules(tolerance: usize) -> Rules {
+  Rules::new(0..=0, 0..tolerance)
+}
+
+// Sets up the seating simulation, where occupied seats are the live cells of
+// `grid`.
+fn simulate(world: &World, grid: SquareGrid, tolerance: usize) -> GridAutomaton {
+  GridAutomaton::new(grid, seating_rules(tolerance), world.full_chairs())
+}
+
+// Builds the simulation for part 1, or for part 2 if `sight` is true.
+fn seating_for_part(world: &World, sight: bool) -> GridAutomaton {
+  let is_chair = world.is_chair();
+  if sight {
+    simulate(world, SquareGrid::line_of_sight(world.width, world.height, &is_chair), 5)
+  } else {
+    simulate(world, SquareGrid::adjacent(world.width, world.height, &is_chair), 4)
+  }
+}
+
+fn p1(input: &str) -> anyhow::Result<usize> {
+  let mut seating = seating_for_part(&World::from_string(input)?, false);
+  seating.run_until_stable();
+  Ok(seating.live_count())
+}
+
+fn p2(input: &str) -> anyhow::Result<usize> {
+  let mut seating = seating_for_part(&World::from_string(input)?, true);
+  seating.run_until_stable();
+  Ok(seating.live_count())
+}
+
+// A single generation of a seating simulation, for drawing.
+struct Frame<'a> {
+  world: &'a World,
+  seating: &'a GridAutomaton,
+  // The seats that changed to reach this generation.
+  changed: &'a HashSet<usize>,
+}
+impl<'a> Frame<'a> {
+  fn spot(&self, p: usize) -> Spot {
+    match self.world.spots[p] {
+      Spot::Floor => Spot::Floor,
+      Spot::Chair(_) => Spot::Chair(self.seating.is_alive(p)),
+    }
+  }
+
+  fn stats(&self) -> String {
+    let seats = self.world.spots.iter().filter(|&&spot| spot != Spot::Floor).count();
+    let occupied = self.seating.live_count();
+    format!(
+      "Generation {}: {} of {} seats occupied ({:.1}%), {} changed",
+      self.seating.generation(),
+      occupied,
+      seats,
+      100.0 * occupied as f64 / std::cmp::max(seats, 1) as f64,
+      self.changed.len()
+    )
+  }
+
+  // Draws the frame for a terminal, with seats that changed in bold colour:
+  // yellow for those just sat in and red for those just left.
+  fn to_ansi(&self) -> String {
+    let mut s = String::new();
+    for y in 0..self.world.height {
+      for x in 0..self.world.width {
+        let p = x + y * self.world.width;
+        let c = match self.spot(p) {
+          Spot::Chair(true) => '#',
+          Spot::Chair(false) => 'L',
+          Spot::Floor => '.',
+        };
+        match (self.changed.contains(&p), self.spot(p)) {
+          (true, Spot::Chair(true)) => s += &format!("\x1b[1;33m{}\x1b[0m", c),
+          (true, _) => s += &format!("\x1b[1;31m{}\x1b[0m", c),
+          (false, _) => s.push(c),
         }
       }
+      s.push('\n');
     }
+    s + &self.stats() + "\n"
+  }
 
-    world = next_world;
-    if !changed { break; }
-    changed = false;
+  // Draws the frame as a binary PPM image, with each spot `scale` pixels
+  // across, using the same colours as the terminal for changed seats.
+  fn to_ppm(&self, scale: usize) -> Vec<u8> {
+    let (w, h) = (self.world.width * scale, self.world.height * scale);
+    let mut image = format!("P6\n{} {}\n255\n", w, h).into_bytes();
+    for py in 0..h {
+      for px in 0..w {
+        let p = px / scale + py / scale * self.world.width;
+        let rgb: [u8; 3] = match (self.changed.contains(&p), self.spot(p)) {
+          (_, Spot::Floor) => [32, 32, 32],
+          (true, Spot::Chair(true)) => [255, 220, 0],
+          (true, Spot::Chair(false)) => [220, 40, 40],
+          (false, Spot::Chair(true)) => [40, 160, 60],
+          (false, Spot::Chair(false)) => [150, 150, 150],
+        };
+        image.extend_from_slice(&rgb);
+      }
+    }
+    image
+  }
+}
+
+// Runs the simulation until it is stable, calling `draw` with the starting
+// state and then with each generation that changed something.
+fn animate<F>(world: &World, mut seating: GridAutomaton, mut draw: F) -> anyhow::Result<()>
+where
+  F: FnMut(&Frame) -> anyhow::Result<()>,
+{
+  let mut changed = HashSet::new();
+  loop {
+    draw(&Frame {
+      world,
+      seating: &seating,
+      changed: &changed,
+    })?;
+    changed = seating.step().into_iter().collect();
+    if changed.is_empty() {
+      return Ok(());
+    }
+  }
+}
+
+fn main() -> anyhow::Result<()> {
+  let file = std::fs::read_to_string(INPUT_FILE)?;
+  // `day11 recount` runs the slower simulation which walks the grid on every
+  // generation.
+  if std::env::args().nth(1).filter(|s| s == "recount").is_some() {
+    let world = World::from_string(&file)?;
+    println!("Part 1 {}", run_until_stable(world.clone(), World::count_neighbours, 4));
+    println!("Part 2 {}", run_until_stable(world, World::count_sight_neighbours, 5));
+    return Ok(());
+  }
+
+  // `day11 watch [sight] [DELAY_MS]` animates the simulation in the terminal,
+  // and `day11 frames DIR [sight] [SCALE]` writes each generation to a
+  // numbered PPM image in DIR. The part 1 rules are used unless `sight` is
+  // given.
+  let args = std::env::args().skip(1).collect::<Vec<_>>();
+  let sight = args.iter().any(|s| s == "sight");
+  let number_arg = |default: u64| -> anyhow::Result<u64> {
+    match args.iter().skip(1).filter(|s| *s != "sight").find(|s| s.parse::<u64>().is_ok()) {
+      Some(s) => Ok(s.parse()?),
+      None => Ok(default),
+    }
+  };
+  match args.first().map(|s| s.as_str()) {
+    Some("watch") => {
+      let delay = std::time::Duration::from_millis(number_arg(100)?);
+      let world = World::from_string(&file)?;
+      return animate(&world, seating_for_part(&world, sight), |frame| {
+        // Clear the screen and draw from the top left.
+        print!("\x1b[2J\x1b[H{}", frame.to_ansi());
+        std::thread::sleep(delay);
+        Ok(())
+      });
+    }
+    Some("frames") => {
+      let dir = std::path::PathBuf::from(args.get(1).ok_or_else(|| anyhow!("frames needs a directory"))?);
+      std::fs::create_dir_all(&dir)?;
+      let scale = number_arg(4)? as usize;
+      let world = World::from_string(&file)?;
+      return animate(&world, seating_for_part(&world, sight), |frame| {
+        let path = dir.join(format!("frame{:04}.ppm", frame.seating.generation()));
+        std::fs::write(&path, frame.to_ppm(scale))?;
+        println!("{}: {}", path.display(), frame.stats());
+        Ok(())
+      });
+    }
+    _ => (),
   }
-  println!("Part 2 {}", world.count_full_chairs());
-}
\ No newline at end of file
+  println!("Part 1 {}", p1(&file)?);
+  println!("Part 2 {}", p2(&file)?);
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const TEST_INPUT: &str = r#"L.LL.LL.LL
+LLLLLLL.LL
+L.L.L..L..
+LLLL.LL.LL
+L.LL.LL.LL
+L.LLLLL.LL
+..L.L.....
+LLLLLLLLLL
+L.LLLLLL.L
+L.LLLLL.LL
+"#;
+
+  #[test]
+  fn test_parts() -> anyhow::Result<()> {
+    assert_eq!(p1(TEST_INPUT)?, 37);
+    assert_eq!(p2(TEST_INPUT)?, 26);
+    Ok(())
+  }
+
+  #[test]
+  fn test_from_string() -> anyhow::Result<()> {
+    let world = World::from_string(TEST_INPUT)?;
+    assert_eq!((world.width, world.height), (10, 10));
+    assert!(world.get(9, 9) == Spot::Chair(false));
+    assert!(world.get(1, 0) == Spot::Floor);
+
+    let world = World::from_string(".#\n##\nL.\n")?;
+    assert_eq!((world.width, world.height), (2, 3));
+    assert_eq!(world.count_neighbours(0, 2), 2);
+
+    assert!(World::from_string("LL\nL\n").is_err());
+    assert!(World::from_string("LL\nLx\n").is_err());
+    assert!(World::from_string("\n").is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_grid() -> anyhow::Result<()> {
+    let world = World::from_string("L.L\n...\nL#L\n")?;
+    let adjacent = SquareGrid::adjacent(world.width, world.height, &world.is_chair());
+    let mut n = adjacent.neighbours_of(7).to_vec();
+    n.sort();
+    assert_eq!(n, vec![6, 8]);
+    assert!(adjacent.neighbours_of(0).is_empty());
+    assert!(adjacent.neighbours_of(1).is_empty());
+
+    let sight = SquareGrid::line_of_sight(world.width, world.height, &world.is_chair());
+    let mut n = sight.neighbours_of(0).to_vec();
+    n.sort();
+    assert_eq!(n, vec![2, 6, 8]);
+
+    // The top corners can't see the occupied seat, so they fill, and then the
+    // bottom corners can see occupied seats.
+    let mut seating = simulate(&world, sight, 2);
+    assert_eq!(seating.live_count(), 1);
+    let mut changed = seating.step();
+    changed.sort();
+    assert_eq!(changed, vec![0, 2]);
+    assert_eq!(seating.live_count(), 3);
+    assert_eq!(seating.run_until_stable(), 0);
+    assert_eq!(seating.generation(), 2);
+    Ok(())
+  }
+
+  #[test]
+  fn test_animate() -> anyhow::Result<()> {
+    let world = World::from_string(TEST_INPUT)?;
+    let mut frames = Vec::new();
+    animate(&world, seating_for_part(&world, false), |frame| {
+      frames.push((frame.to_ansi(), frame.to_ppm(2)));
+      Ok(())
+    })?;
+    // The example settles after 5 generations of changes.
+    assert_eq!(frames.len(), 6);
+    let (first_ansi, first_ppm) = &frames[0];
+    assert!(first_ansi.starts_with("L.LL.LL.LL\n"));
+    assert!(first_ansi.ends_with("Generation 0: 0 of 71 seats occupied (0.0%), 0 changed\n"));
+    assert!(first_ppm.starts_with(b"P6\n20 20\n255\n"));
+    assert_eq!(first_ppm.len(), b"P6\n20 20\n255\n".len() + 20 * 20 * 3);
+
+    // Everyone sits down in generation 1, highlighted in yellow.
+    let (ansi, _) = &frames[1];
+    assert!(ansi.starts_with("\x1b[1;33m#\x1b[0m.\x1b[1;33m#\x1b[0m"));
+    assert!(ansi.ends_with("Generation 1: 71 of 71 seats occupied (100.0%), 71 changed\n"));
+    let (ansi, _) = &frames[5];
+    assert!(ansi.ends_with("Generation 5: 37 of 71 seats occupied (52.1%), 7 changed\n"));
+    Ok(())
+  }
+
+  #[test]
+  fn test_against_recounting() -> anyhow::Result<()> {
+    let input = std::fs::read_to_string(INPUT_FILE)?;
+    for input in &[TEST_INPUT, &input] {
+      let world = World::from_string(input)?;
+      assert_eq!(p1(input)?, run_until_stable(world.clone(), World::count_neighbours, 4));
+      assert_eq!(p2(input)?, run_until_stable(world, World::count_sight_neighbours, 5));
+    }
+    Ok(())
+  }
+}
@@ -8,6 +8,48 @@ fn main() -> anyhow::Result<()> {
   //let input_all = r"F10 N3 F7 R90 F11";
 
   let actions = parse_actions(&input_all)?;
+
+  // `day12 trace [waypoint]` prints the ship's state after every action,
+  // `day12 csv [waypoint]` and `day12 svg [waypoint]` export its route, and
+  // `day12 stats [waypoint]` summarizes the route.
+  let args = std::env::args().skip(1).collect::<Vec<_>>();
+  let mode = if args.iter().any(|s| s == "waypoint") {
+    Mode::Waypoint
+  } else {
+    Mode::Direct
+  };
+  match args.first().map(|s| s.as_str()) {
+    Some("trace") => {
+      for state in Ship::for_part(mode).navigate(&actions)? {
+        println!("{}", state.describe(mode));
+      }
+      return Ok(());
+    }
+    Some("csv") => {
+      print!(
+        "{}",
+        Route::record(Ship::for_part(mode), &actions)?.to_csv()
+      );
+      return Ok(());
+    }
+    Some("svg") => {
+      print!(
+        "{}",
+        Route::record(Ship::for_part(mode), &actions)?.to_svg()
+      );
+      return Ok(());
+    }
+    Some("stats") => {
+      let route = Route::record(Ship::for_part(mode), &actions)?;
+      let (min, max) = route.bounding_box();
+      println!("Bounding box {} to {}", min, max);
+      println!("Total distance {:.1}", route.total_distance());
+      println!("End {} from the start", route.end().manhattan_distance());
+      return Ok(());
+    }
+    _ => (),
+  }
+
   p1(&actions)?;
   p2(&actions)?;
   Ok(())
@@ -58,7 +100,7 @@ impl std::fmt::Display for Action {
 fn parse_actions(input_all: &str) -> anyhow::Result<Vec<Action>> {
   let mut v = Vec::<Action>::new();
   for l in input_all.split_whitespace() {
-    let action = match l.chars().nth(0).unwrap() {
+    let action = match l.chars().next().unwrap() {
       'N' => Action::GoNorth(l[1..].parse::<u32>()?),
       'S' => Action::GoSouth(l[1..].parse::<u32>()?),
       'E' => Action::GoEast(l[1..].parse::<u32>()?),
@@ -73,27 +115,106 @@ fn parse_actions(input_all: &str) -> anyhow::Result<Vec<Action>> {
   Ok(v)
 }
 
-fn turn(d: Direction, a: Action) -> anyhow::Result<Direction> {
-  match a {
-    Action::TurnLeft(0) => Ok(d),
-    Action::TurnRight(0) => Ok(d),
-    Action::TurnLeft(deg) => {
-      if deg > 360 || deg % 90 > 0 {
-        return Err(anyhow!("Bad input degree {}", deg));
-      }
-      let turned = match d {
-        Direction::North => Direction::West,
-        Direction::West => Direction::South,
-        Direction::South => Direction::East,
-        Direction::East => Direction::North,
-      };
-      turn(turned, Action::TurnLeft(deg - 90))
+// A 2D vector, with x going East and y going North.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+struct Vec2 {
+  x: i64,
+  y: i64,
+}
+impl Vec2 {
+  const fn new(x: i64, y: i64) -> Self {
+    Vec2 { x, y }
+  }
+
+  fn manhattan_distance(self) -> u64 {
+    self.x.unsigned_abs() + self.y.unsigned_abs()
+  }
+
+  // Rotates counter-clockwise (to the left) by `degrees`, which may be
+  // negative to rotate to the right, and must be a multiple of 90.
+  fn rotated(self, degrees: i64) -> anyhow::Result<Vec2> {
+    if degrees % 90 != 0 {
+      return Err(anyhow!("Bad input degree {}", degrees));
     }
-    Action::TurnRight(deg) => turn(d, Action::TurnLeft(360 - deg)),
-    _ => Ok(d),
+    Ok(match degrees.rem_euclid(360) / 90 {
+      0 => self,
+      1 => Vec2::new(-self.y, self.x),
+      2 => Vec2::new(-self.x, -self.y),
+      _ => Vec2::new(self.y, -self.x),
+    })
+  }
+}
+impl std::ops::Add for Vec2 {
+  type Output = Vec2;
+  fn add(self, other: Vec2) -> Vec2 {
+    Vec2::new(self.x + other.x, self.y + other.y)
+  }
+}
+impl std::ops::AddAssign for Vec2 {
+  fn add_assign(&mut self, other: Vec2) {
+    *self = *self + other;
+  }
+}
+impl std::ops::Mul<i64> for Vec2 {
+  type Output = Vec2;
+  fn mul(self, n: i64) -> Vec2 {
+    Vec2::new(self.x * n, self.y * n)
+  }
+}
+impl std::fmt::Display for Vec2 {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+    write!(f, "({}, {})", self.x, self.y)
   }
 }
 
+impl Direction {
+  fn unit(self) -> Vec2 {
+    match self {
+      Direction::North => Vec2::new(0, 1),
+      Direction::South => Vec2::new(0, -1),
+      Direction::East => Vec2::new(1, 0),
+      Direction::West => Vec2::new(-1, 0),
+    }
+  }
+
+  fn from_unit(v: Vec2) -> Option<Direction> {
+    [Direction::North, Direction::South, Direction::East, Direction::West]
+      .iter()
+      .copied()
+      .find(|d| d.unit() == v)
+  }
+}
+
+impl Action {
+  // How far the action turns to the left, with turns to the right being
+  // negative. Zero for actions that don't turn.
+  fn turn_degrees(self) -> i64 {
+    match self {
+      Action::TurnLeft(deg) => deg as i64,
+      Action::TurnRight(deg) => -(deg as i64),
+      _ => 0,
+    }
+  }
+
+  // The direction and distance of an action that moves in a compass
+  // direction.
+  fn compass_move(self) -> Option<(Direction, u32)> {
+    match self {
+      Action::GoNorth(dist) => Some((Direction::North, dist)),
+      Action::GoSouth(dist) => Some((Direction::South, dist)),
+      Action::GoEast(dist) => Some((Direction::East, dist)),
+      Action::GoWest(dist) => Some((Direction::West, dist)),
+      _ => None,
+    }
+  }
+}
+
+// Turns a compass direction by a turning action.
+#[cfg(test)]
+fn turn(d: Direction, a: Action) -> anyhow::Result<Direction> {
+  Ok(Direction::from_unit(d.unit().rotated(a.turn_degrees())?).unwrap())
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -149,129 +270,332 @@ mod tests {
     assert!(turn(E, Action::TurnRight(51)).is_err());
     Ok(())
   }
-}
 
-fn move_delta(dir: Direction, action: Action) -> (i32, i32) {
-  match action {
-    Action::GoNorth(dist) => (0, dist as i32),
-    Action::GoSouth(dist) => (0, -(dist as i32)),
-    Action::GoEast(dist) => (dist as i32, 0),
-    Action::GoWest(dist) => (-(dist as i32), 0),
-    Action::GoForward(dist) => match dir {
-      Direction::North => move_delta(dir, Action::GoNorth(dist)),
-      Direction::South => move_delta(dir, Action::GoSouth(dist)),
-      Direction::East => move_delta(dir, Action::GoEast(dist)),
-      Direction::West => move_delta(dir, Action::GoWest(dist)),
-    },
-    Action::TurnLeft(_) | Action::TurnRight(_) => (0, 0),
+  #[test]
+  fn test_rotated() -> anyhow::Result<()> {
+    let v = Vec2::new(10, 4);
+    assert_eq!(v.rotated(90)?, Vec2::new(-4, 10));
+    assert_eq!(v.rotated(-90)?, Vec2::new(4, -10));
+    assert_eq!(v.rotated(450)?, Vec2::new(-4, 10));
+    assert_eq!(v.rotated(-540)?, Vec2::new(-10, -4));
+    assert_eq!(v.rotated(720)?, v);
+    assert!(v.rotated(45).is_err());
+    assert_eq!(turn(Direction::North, Action::TurnRight(450))?, Direction::East);
+    Ok(())
   }
-}
 
-struct Step {
-  dir: Direction,
-  x: i32,
-  y: i32,
+  const TEST_INPUT: &str = "F10\nN3\nF7\nR90\nF11\n";
+
+  #[test]
+  fn test_navigate() -> anyhow::Result<()> {
+    let actions = parse_actions(TEST_INPUT)?;
+    let mut ship = Ship::new(Mode::Direct, Direction::East.unit());
+    ship.navigate(&actions)?;
+    assert_eq!(ship.position, Vec2::new(17, -8));
+    assert_eq!(ship.position.manhattan_distance(), 25);
+
+    let mut ship = Ship::new(Mode::Waypoint, Vec2::new(10, 1));
+    let trace = ship.navigate(&actions)?;
+    assert_eq!(ship.position.manhattan_distance(), 286);
+    let waypoints = trace.iter().map(|s| s.heading).collect::<Vec<_>>();
+    assert_eq!(
+      waypoints,
+      vec![
+        Vec2::new(10, 1),
+        Vec2::new(10, 4),
+        Vec2::new(10, 4),
+        Vec2::new(4, -10),
+        Vec2::new(4, -10)
+      ]
+    );
+    assert_eq!(trace[2].position, Vec2::new(170, 38));
+    assert_eq!(trace[4].action, Action::GoForward(11));
+    assert_eq!(trace[3].describe(Mode::Waypoint), "TurnRight 90: ship at (170, 38) waypoint (4, -10)");
+    let trace = Ship::new(Mode::Direct, Direction::East.unit()).navigate(&actions)?;
+    assert_eq!(trace[3].describe(Mode::Direct), "TurnRight 90: ship at (17, 3) facing South");
+    Ok(())
+  }
+
+  #[test]
+  fn test_route() -> anyhow::Result<()> {
+    let actions = parse_actions(TEST_INPUT)?;
+    let route = Route::record(Ship::for_part(Mode::Direct), &actions)?;
+    assert_eq!(route.bounding_box(), (Vec2::new(0, -8), Vec2::new(17, 3)));
+    assert_eq!(route.total_distance(), 10.0 + 3.0 + 7.0 + 11.0);
+    assert_eq!(
+      route.to_csv(),
+      "step,action,ship_x,ship_y,heading_x,heading_y
+0,start,0,0,1,0
+1,GoForward 10,10,0,1,0
+2,GoNorth 3,10,3,1,0
+3,GoForward 7,17,3,1,0
+4,TurnRight 90,17,3,0,-1
+5,GoForward 11,17,-8,0,-1
+"
+    );
+    assert!(route
+      .to_svg()
+      .contains("<polyline points=\"0,0 10,0 10,-3 17,-3 17,-3 17,8\""));
+
+    let route = Route::record(Ship::for_part(Mode::Waypoint), &actions)?;
+    assert_eq!(route.end(), Vec2::new(214, -72));
+    assert_eq!(
+      route.bounding_box(),
+      (Vec2::new(0, -82), Vec2::new(218, 42))
+    );
+    assert!(route
+      .to_csv()
+      .ends_with("\n5,GoForward 11,214,-72,218,-82\n"));
+    let svg = route.to_svg();
+    assert_eq!(svg.matches("<line ").count(), 6);
+    assert!(
+      svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"-11 -53 240 146\">")
+    );
+
+    let mut ship = Ship::for_part(Mode::Direct);
+    ship.position = Vec2::new(5, 2);
+    let svg = Route::record(ship, &actions)?.to_svg();
+    assert!(svg.contains("<circle cx=\"5\" cy=\"-2\" r="));
+    assert!(svg.contains("<circle cx=\"22\" cy=\"6\" r="));
+    Ok(())
+  }
 }
 
-fn perform_action(step: Step, action: Action) -> anyhow::Result<Step> {
-  let dir = turn(step.dir, action)?;
-  let (xd, yd) = move_delta(step.dir, action);
-  Ok(Step {
-    dir: dir,
-    x: step.x + xd,
-    y: step.y + yd,
-  })
+// How a ship's compass moves (N, S, E and W) are applied.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Mode {
+  // They move the ship, and the heading is the direction the ship faces.
+  Direct,
+  // They move a waypoint, and the heading is the waypoint's offset from the
+  // ship.
+  Waypoint,
 }
 
-fn manhattan_distance(x: i32, y: i32) -> u32 {
-  (x.abs() + y.abs()) as u32
+// The state of a ship after performing an action.
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct ShipState {
+  action: Action,
+  position: Vec2,
+  heading: Vec2,
 }
 
-fn p1(actions: &Vec<Action>) -> anyhow::Result<()> {
-  let mut dir = Direction::East;
-  let mut x = 0;
-  let mut y = 0;
-  for action in actions {
-    let step = perform_action(
-      Step {
-        dir: dir,
-        x: x,
-        y: y,
-      },
-      *action,
-    )?;
-    dir = step.dir;
-    x = step.x;
-    y = step.y;
-  }
-  println!("Part 1 {}", manhattan_distance(x, y));
-  Ok(())
+impl ShipState {
+  fn describe(&self, mode: Mode) -> String {
+    match (mode, Direction::from_unit(self.heading)) {
+      (Mode::Direct, Some(dir)) => format!("{}: ship at {} facing {}", self.action, self.position, dir),
+      (Mode::Direct, None) => format!("{}: ship at {} heading {}", self.action, self.position, self.heading),
+      (Mode::Waypoint, _) => format!("{}: ship at {} waypoint {}", self.action, self.position, self.heading),
+    }
+  }
 }
 
-struct Step2 {
-  x: i32,
-  y: i32,
-  waypoint_dx: i32,
-  waypoint_dy: i32,
+struct Ship {
+  mode: Mode,
+  position: Vec2,
+  // Where the ship goes when moving forward by 1.
+  heading: Vec2,
 }
+impl Ship {
+  fn new(mode: Mode, heading: Vec2) -> Self {
+    Ship {
+      mode,
+      position: Vec2::default(),
+      heading,
+    }
+  }
 
-fn turn_waypoint(x: i32, y: i32, a: Action) -> anyhow::Result<(i32, i32)> {
-  match a {
-    Action::TurnLeft(0) | Action::TurnRight(0) => Ok((x, y)),
-    Action::TurnLeft(deg) => turn_waypoint(x, y, Action::TurnRight(360 - deg)),
-    Action::TurnRight(deg) => {
-      if deg > 360 || deg % 90 > 0 {
-        return Err(anyhow!("Bad input degree {}", deg));
+  fn perform(&mut self, action: Action) -> anyhow::Result<ShipState> {
+    if let Some((dir, dist)) = action.compass_move() {
+      let delta = dir.unit() * dist as i64;
+      match self.mode {
+        Mode::Direct => self.position += delta,
+        Mode::Waypoint => self.heading += delta,
       }
-      let (x, y) = (y, -x); // 90 degree rotation to the right.
-      turn_waypoint(x, y, Action::TurnRight(deg - 90))
+    } else if let Action::GoForward(dist) = action {
+      self.position += self.heading * dist as i64;
+    } else {
+      self.heading = self.heading.rotated(action.turn_degrees())?;
     }
-    _ => Ok((x, y)),
+    Ok(ShipState {
+      action,
+      position: self.position,
+      heading: self.heading,
+    })
   }
+
+  // The ship's starting state for each part of the puzzle.
+  fn for_part(mode: Mode) -> Self {
+    match mode {
+      Mode::Direct => Ship::new(Mode::Direct, Direction::East.unit()),
+      // Waypoint starts North 1, East 10 compared to the ship.
+      Mode::Waypoint => Ship::new(Mode::Waypoint, Vec2::new(10, 1)),
+    }
+  }
+
+  // Performs each action in turn, returning the state after each one.
+  fn navigate(&mut self, actions: &[Action]) -> anyhow::Result<Vec<ShipState>> {
+    actions.iter().map(|&action| self.perform(action)).collect()
+  }
+}
+
+// The full path a ship took, for plotting.
+struct Route {
+  mode: Mode,
+  // The position and heading before the first action.
+  start: (Vec2, Vec2),
+  steps: Vec<ShipState>,
 }
+impl Route {
+  fn record(mut ship: Ship, actions: &[Action]) -> anyhow::Result<Self> {
+    let start = (ship.position, ship.heading);
+    let steps = ship.navigate(actions)?;
+    Ok(Route {
+      mode: ship.mode,
+      start,
+      steps,
+    })
+  }
+
+  // The position and heading at the start and after each action.
+  fn points(&self) -> impl Iterator<Item = (Vec2, Vec2)> + '_ {
+    std::iter::once(self.start).chain(self.steps.iter().map(|s| (s.position, s.heading)))
+  }
+
+  fn end(&self) -> Vec2 {
+    self.points().last().unwrap().0
+  }
+
+  // The positions that get drawn: the ship's, and the waypoint's when there
+  // is one.
+  fn drawn_positions(&self) -> Vec<Vec2> {
+    let mut v = Vec::new();
+    for (position, heading) in self.points() {
+      v.push(position);
+      if self.mode == Mode::Waypoint {
+        v.push(position + heading);
+      }
+    }
+    v
+  }
+
+  // The smallest and largest corners of the box containing every drawn
+  // position.
+  fn bounding_box(&self) -> (Vec2, Vec2) {
+    let positions = self.drawn_positions();
+    let min = Vec2::new(
+      positions.iter().map(|p| p.x).min().unwrap(),
+      positions.iter().map(|p| p.y).min().unwrap(),
+    );
+    let max = Vec2::new(
+      positions.iter().map(|p| p.x).max().unwrap(),
+      positions.iter().map(|p| p.y).max().unwrap(),
+    );
+    (min, max)
+  }
 
-fn perform_action2(step: Step2, action: Action) -> anyhow::Result<Step2> {
-  let (waypoint_dx, waypoint_dy) = turn_waypoint(step.waypoint_dx, step.waypoint_dy, action)?;
-  match action {
-    Action::GoForward(dist) => Ok(Step2 {
-      x: step.x + waypoint_dx * dist as i32,
-      y: step.y + waypoint_dy * dist as i32,
-      waypoint_dx: waypoint_dx,
-      waypoint_dy: waypoint_dy,
-    }),
-    _ => {
-      let (xd, yd) = move_delta(/*unused*/ Direction::North, action);
-      Ok(Step2 {
-        x: step.x,
-        y: step.y,
-        waypoint_dx: waypoint_dx + xd,
-        waypoint_dy: waypoint_dy + yd,
+  // The length of the path the ship sailed.
+  fn total_distance(&self) -> f64 {
+    let positions = self
+      .points()
+      .map(|(position, _)| position)
+      .collect::<Vec<_>>();
+    positions
+      .windows(2)
+      .map(|pair| {
+        (((pair[1].x - pair[0].x) as f64).powi(2) + ((pair[1].y - pair[0].y) as f64).powi(2)).sqrt()
       })
+      .sum()
+  }
+
+  // One row for the start and after each action, with the waypoint's
+  // absolute position in waypoint mode or the ship's heading otherwise.
+  fn to_csv(&self) -> String {
+    let mut csv = match self.mode {
+      Mode::Direct => "step,action,ship_x,ship_y,heading_x,heading_y\n".to_string(),
+      Mode::Waypoint => "step,action,ship_x,ship_y,waypoint_x,waypoint_y\n".to_string(),
+    };
+    let actions = std::iter::once(None).chain(self.steps.iter().map(|s| Some(s.action)));
+    for (i, ((position, heading), action)) in self.points().zip(actions).enumerate() {
+      let second = match self.mode {
+        Mode::Direct => heading,
+        Mode::Waypoint => position + heading,
+      };
+      let action = action.map_or("start".to_string(), |a| a.to_string());
+      csv += &format!(
+        "{},{},{},{},{},{}\n",
+        i, action, position.x, position.y, second.x, second.y
+      );
+    }
+    csv
+  }
+
+  // Draws the ship's path as a polyline, and in waypoint mode a line from the
+  // ship to the waypoint after every action. North is up.
+  fn to_svg(&self) -> String {
+    let (min, max) = self.bounding_box();
+    let size = std::cmp::max(std::cmp::max(max.x - min.x, max.y - min.y), 1);
+    let margin = size / 20 + 1;
+    let stroke = size as f64 / 400.0;
+    // SVG's y axis points down, so flip it.
+    let to_svg = |p: Vec2| format!("{},{}", p.x, -p.y);
+
+    let mut svg = format!(
+      "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">\n",
+      min.x - margin,
+      -max.y - margin,
+      max.x - min.x + 2 * margin,
+      max.y - min.y + 2 * margin
+    );
+    if self.mode == Mode::Waypoint {
+      for (position, heading) in self.points() {
+        svg += &format!(
+          "  <line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"orange\" stroke-width=\"{}\" opacity=\"0.5\"/>\n",
+          position.x,
+          -position.y,
+          (position + heading).x,
+          -(position + heading).y,
+          stroke / 2.0
+        );
+      }
     }
+    let points = self
+      .points()
+      .map(|(position, _)| to_svg(position))
+      .collect::<Vec<_>>();
+    svg += &format!(
+      "  <polyline points=\"{}\" fill=\"none\" stroke=\"navy\" stroke-width=\"{}\"/>\n",
+      points.join(" "),
+      stroke
+    );
+    let start = self.start.0;
+    svg += &format!(
+      "  <circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"green\"/>\n",
+      start.x,
+      -start.y,
+      stroke * 3.0
+    );
+    let end = self.end();
+    svg += &format!(
+      "  <circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"red\"/>\n",
+      end.x,
+      -end.y,
+      stroke * 3.0
+    );
+    svg += "</svg>\n";
+    svg
   }
 }
 
-fn p2(actions: &Vec<Action>) -> anyhow::Result<()> {
-  let mut ship_x = 0;
-  let mut ship_y = 0;
-  // Waypoint starts North 1, East 10 compared to the ship.
-  let mut waypoint_dx = 10;
-  let mut waypoint_dy = 1;
-  for action in actions {
-    let step = perform_action2(
-      Step2 {
-        x: ship_x,
-        y: ship_y,
-        waypoint_dx: waypoint_dx,
-        waypoint_dy: waypoint_dy,
-      },
-      *action,
-    )?;
-    ship_x = step.x;
-    ship_y = step.y;
-    waypoint_dx = step.waypoint_dx;
-    waypoint_dy = step.waypoint_dy;
-  }
-  println!("Part 2 {}", manhattan_distance(ship_x, ship_y));
+fn p1(actions: &[Action]) -> anyhow::Result<()> {
+  let mut ship = Ship::for_part(Mode::Direct);
+  ship.navigate(actions)?;
+  println!("Part 1 {}", ship.position.manhattan_distance());
+  Ok(())
+}
+
+fn p2(actions: &[Action]) -> anyhow::Result<()> {
+  let mut ship = Ship::for_part(Mode::Waypoint);
+  ship.navigate(actions)?;
+  println!("Part 2 {}", ship.position.manhattan_distance());
   Ok(())
 }
@@ -1,79 +1,211 @@
 #[macro_use]
 extern crate anyhow;
 
+mod number_theory;
+use number_theory::crt;
+
 static INPUT_FILE: &str = "day13/input.txt";
 
+fn parse_notes(input_all: &str) -> anyhow::Result<(i64, Vec<Option<i64>>)> {
+  let mut iter = input_all.split_terminator("\n");
+  let start_time = iter.next().ok_or(anyhow!("No leave time"))?.parse::<i64>()?;
+  let bus_ids : Vec<_> = iter.next().ok_or(anyhow!("No bus ids"))?.split_terminator(",").map(|x| x.parse::<i64>().ok()).collect();
+  // Buses leave at multiples of their id, which needs it to be positive.
+  if let Some(id) = bus_ids.iter().flatten().find(|&&id| id <= 0) {
+    return Err(anyhow!("Bad bus id {}, ids must be positive", id));
+  }
+  Ok((start_time, bus_ids))
+}
+
 fn main() -> anyhow::Result<()> {
   let input_all = std::fs::read_to_string(INPUT_FILE)?;
   //let input_all = "1\n67,7,59,61";
 
-  let (start_time, bus_ids) = {
-    let mut iter = input_all.split_terminator("\n");
-    let start_time = iter.next().ok_or(anyhow!("No leave time"))?.parse::<i64>()?;
-    let bus_ids : Vec<_> = iter.next().ok_or(anyhow!("No bus ids"))?.split_terminator(",").map(|x| x.parse::<i64>().ok()).collect();
-    (start_time, bus_ids)
-  };
+  let (start_time, bus_ids) = parse_notes(&input_all)?;
 
-  p1(start_time, &bus_ids)?;
-  p2(start_time, &bus_ids)?;
-  Ok(())
-}
-
-fn p1(start_time: i64, bus_ids: &Vec<Option<i64>>) -> anyhow::Result<()> {
-  let mut bus_times: Vec<(i64, i64)> = bus_ids.iter().filter_map(|&opt| opt.map(|i| (i, i))).collect();
-  loop {
-    let mut changed = false;
-    for b in &mut bus_times {
-      if b.1 < start_time {
-        b.1 += b.0;
-        changed = true;
+  // Queries on the timetable:
+  // `day13 next TIME` lists each bus's next departure at or after TIME.
+  // `day13 align [N] BUS+OFFSET...` lists the first N (default 1) times t
+  //   where each BUS departs at t + OFFSET.
+  let args = std::env::args().skip(1).collect::<Vec<_>>();
+  let timetable = Timetable::new(bus_ids.clone());
+  match args.first().map(|s| s.as_str()) {
+    Some("next") => {
+      let time = args.get(1).ok_or(anyhow!("next needs a time"))?.parse()?;
+      for (id, departure) in timetable.next_departures(time) {
+        println!("Bus {} departs at {} (wait {})", id, departure, departure - time);
+      }
+      return Ok(());
+    }
+    Some("align") => {
+      let (count, schedule_args) = match args.get(1).map(|s| s.parse::<usize>()) {
+        Some(Ok(count)) => (count, &args[2..]),
+        _ => (1, &args[1..]),
+      };
+      let schedule = schedule_args.iter().map(|s| parse_scheduled_bus(s)).collect::<anyhow::Result<Vec<_>>>()?;
+      for time in timetable.alignment_times(&schedule, count)? {
+        println!("{}", time);
       }
+      return Ok(());
     }
-    if !changed { break; }
+    _ => (),
   }
 
-  bus_times.sort_by_key(|bus| bus.1);  // Sort by time the bus leaves.
-  let chosen_bus = &bus_times[0];
-  println!("Part 1 {}", chosen_bus.0 * (chosen_bus.1 - start_time));
-
+  println!("Part 1 {}", p1(start_time, &bus_ids)?);
+  println!("Part 2 {}", p2(start_time, &bus_ids)?);
   Ok(())
 }
 
-fn merge_buses(cycle_start: i64, cycle_len: i64, offset_id2: (i64, i64)) -> (i64, i64) {
-  let (offset, id2) = offset_id2;
-  
-  let mut start_time = cycle_start + offset;
-  // If time % id2 is the amount of time in the route at `time`
-  // that has passed since the `id2` bus left. id2 subtract that
-  // time is the time until it gets back. If it gets back at `offset`
-  // then it fits the schedule.
-  let time_left = |time, bus_id| {
-    if time % bus_id == 0 {
-      0
-    } else {
-      bus_id - time % bus_id
+// Parses `BUS+OFFSET` into (bus, offset), where the offset may be negative.
+fn parse_scheduled_bus(s: &str) -> anyhow::Result<(i64, i64)> {
+  let split = s.find(['+', '-']).ok_or(anyhow!("Expected BUS+OFFSET, got `{}`", s))?;
+  Ok((s[..split].parse()?, s[split..].trim_start_matches('+').parse()?))
+}
+
+// How long after `time` the bus next leaves. Buses leave at every multiple of
+// their id, so that's the amount needed to reach the next multiple.
+fn wait_time(time: i64, bus_id: i64) -> i64 {
+  (-time).rem_euclid(bus_id)
+}
+
+struct Timetable {
+  // Bus ids in the order of the notes, with None for each `x`.
+  bus_ids: Vec<Option<i64>>,
+}
+impl Timetable {
+  fn new(bus_ids: Vec<Option<i64>>) -> Self {
+    Timetable { bus_ids }
+  }
+
+  fn buses(&self) -> impl Iterator<Item = i64> + '_ {
+    self.bus_ids.iter().flatten().copied()
+  }
+
+  // The first time at or after `time` that each bus departs, as (bus,
+  // departure) pairs, soonest first.
+  fn next_departures(&self, time: i64) -> Vec<(i64, i64)> {
+    let mut departures = self.buses().map(|id| (id, time + wait_time(time, id))).collect::<Vec<_>>();
+    departures.sort_by_key(|&(id, departure)| (departure, id));
+    departures
+  }
+
+  // The schedule from the notes, where each bus departs as many minutes after
+  // the first as its position in the list.
+  fn puzzle_schedule(&self) -> Vec<(i64, i64)> {
+    self
+      .bus_ids
+      .iter()
+      .enumerate()
+      .filter_map(|(i, maybe_id)| maybe_id.map(|id| (id, i as i64)))
+      .collect()
+  }
+
+  // Finds when the buses line up with a schedule of (bus, offset) pairs,
+  // meaning each bus departs `offset` minutes after a time t. Returns the
+  // first such t that is not negative, and the period after which the
+  // buses line up again.
+  //
+  // A bus departing at t + offset means t + offset ≡ 0 (mod bus), or
+  // t ≡ -offset (mod bus), so the schedule is a system of congruences.
+  fn alignment(&self, schedule: &[(i64, i64)]) -> anyhow::Result<(i128, i128)> {
+    for &(id, _) in schedule {
+      if !self.buses().any(|bus| bus == id) {
+        return Err(anyhow!("Bus {} is not in service", id));
+      }
     }
-  };
-  while time_left(start_time, id2) != 0 {
-    start_time += cycle_len;
+    let congruences = schedule.iter().map(|&(id, offset)| (-(offset as i128), id as i128)).collect::<Vec<_>>();
+    crt(&congruences)
   }
-  let mut end_time = start_time + cycle_len;
-  while time_left(end_time, id2) != 0 {
-    end_time += cycle_len;
+
+  // The first `count` times that the buses line up with the schedule.
+  fn alignment_times(&self, schedule: &[(i64, i64)], count: usize) -> anyhow::Result<Vec<i128>> {
+    let (first, period) = self.alignment(schedule)?;
+    Ok((0..count as i128).map(|n| first + n * period).collect())
   }
-  (start_time-offset, end_time-start_time)
 }
 
-fn p2(_start_time: i64, bus_ids: &Vec<Option<i64>>) -> anyhow::Result<()> {
-  let indexed_bus_ids = bus_ids.iter().enumerate().filter_map(|(i, maybe_id)| maybe_id.map(|id| (i, id))).collect::<Vec<_>>();
-  let mut start_time = 0;
-  let mut cycle_len = 1;
-  for (i, maybe_id) in indexed_bus_ids {
-    let (start, len) = merge_buses(start_time, cycle_len, (i as i64, maybe_id));
-    start_time = start;
-    cycle_len = len;
+fn p1(start_time: i64, bus_ids: &[Option<i64>]) -> anyhow::Result<i64> {
+  let timetable = Timetable::new(bus_ids.to_vec());
+  let &(chosen_bus, departure) = timetable.next_departures(start_time).first().ok_or(anyhow!("No buses"))?;
+  Ok(chosen_bus * (departure - start_time))
+}
+
+fn p2(_start_time: i64, bus_ids: &[Option<i64>]) -> anyhow::Result<i128> {
+  let timetable = Timetable::new(bus_ids.to_vec());
+  let (time, _) = timetable.alignment(&timetable.puzzle_schedule())?;
+  Ok(time)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use number_theory::{extended_gcd, mod_inverse};
+
+  #[test]
+  fn test_example() -> anyhow::Result<()> {
+    let (start_time, bus_ids) = parse_notes("939\n7,13,x,x,59,x,31,19\n")?;
+    assert_eq!(p1(start_time, &bus_ids)?, 295);
+    assert_eq!(p2(start_time, &bus_ids)?, 1068781);
+
+    let (start_time, bus_ids) = parse_notes("1\n1789,37,47,1889\n")?;
+    assert_eq!(p2(start_time, &bus_ids)?, 1202161486);
+
+    assert!(parse_notes("939\n7,0,x\n").is_err());
+    assert!(parse_notes("939\n7,x,-13\n").is_err());
+    Ok(())
   }
 
-  println!("Part 2 {}", start_time);
-  Ok(())
-}
\ No newline at end of file
+  #[test]
+  fn test_timetable() -> anyhow::Result<()> {
+    let (_, bus_ids) = parse_notes("939\n7,13,x,x,59,x,31,19\n")?;
+    let timetable = Timetable::new(bus_ids);
+    assert_eq!(
+      timetable.next_departures(939),
+      vec![(59, 944), (7, 945), (13, 949), (19, 950), (31, 961)]
+    );
+    assert_eq!(timetable.next_departures(0)[0], (7, 0));
+
+    // 7 and 13 depart together every 91 minutes, with 13 a minute later
+    // first at 77.
+    assert_eq!(timetable.alignment(&[(7, 0), (13, 1)])?, (77, 91));
+    assert_eq!(timetable.alignment_times(&[(7, 0), (13, 1)], 3)?, vec![77, 168, 259]);
+    // Offsets can be negative, or larger than the bus id.
+    assert_eq!(timetable.alignment(&[(13, -1), (7, 0)])?, (14, 91));
+    assert_eq!(timetable.alignment(&[(7, 15)])?, (6, 7));
+    assert!(timetable.alignment(&[(8, 0)]).is_err());
+    assert_eq!(timetable.alignment(&timetable.puzzle_schedule())?.0, 1068781);
+
+    assert_eq!(parse_scheduled_bus("13+1")?, (13, 1));
+    assert_eq!(parse_scheduled_bus("13-2")?, (13, -2));
+    assert!(parse_scheduled_bus("13").is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_number_theory() -> anyhow::Result<()> {
+    assert_eq!(extended_gcd(240i64, 46), (2, -9, 47));
+    assert_eq!(extended_gcd(-4i64, 6), (2, 1, 1));
+    assert_eq!(extended_gcd(0i64, 5).0, 5);
+    assert_eq!(mod_inverse(3i64, 7), Some(5));
+    assert_eq!(mod_inverse(-3i64, 7), Some(2));
+    assert_eq!(mod_inverse(4i64, 6), None);
+
+    assert_eq!(crt(&[(2i64, 3), (3, 5), (2, 7)])?, (23, 105));
+    // Moduli which share factors.
+    assert_eq!(crt(&[(2i64, 4), (4, 6)])?, (10, 12));
+    assert_eq!(crt(&[(3i64, 6), (3, 6), (1, 2)])?, (3, 6));
+    assert!(crt(&[(1i64, 4), (2, 6)]).is_err());
+    assert!(crt(&[(1i64, 0)]).is_err());
+
+    // Large moduli overflow an i64, but not an i128.
+    let big: [(i64, i64); 3] = [(1, 1_000_000_007), (2, 998_244_353), (3, 1_000_000_009)];
+    assert!(crt(&big).is_err());
+    let big128 = big.iter().map(|&(a, m)| (a as i128, m as i128)).collect::<Vec<_>>();
+    let (x, m) = crt(&big128)?;
+    assert_eq!(m, 1_000_000_007 * 998_244_353 * 1_000_000_009);
+    for (a, modulus) in big128 {
+      assert_eq!(x % modulus, a);
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,115 @@
+// Number theory for solving systems of modular congruences, generic over the
+// integer type so that large moduli can use i128.
+
+use std::fmt::{Debug, Display};
+use std::ops::{Add, Div, Mul, Neg, Rem, Sub};
+
+// The integer operations needed here, implemented for i64 and i128.
+pub trait Int:
+  Copy
+  + Ord
+  + Debug
+  + Display
+  + Add<Output = Self>
+  + Sub<Output = Self>
+  + Mul<Output = Self>
+  + Div<Output = Self>
+  + Rem<Output = Self>
+  + Neg<Output = Self>
+{
+  const ZERO: Self;
+  const ONE: Self;
+  fn checked_add(self, other: Self) -> Option<Self>;
+  fn checked_mul(self, other: Self) -> Option<Self>;
+  fn rem_euclid(self, other: Self) -> Self;
+}
+macro_rules! impl_int {
+  ($($t:ty),*) => {$(
+    impl Int for $t {
+      const ZERO: Self = 0;
+      const ONE: Self = 1;
+      fn checked_add(self, other: Self) -> Option<Self> {
+        <$t>::checked_add(self, other)
+      }
+      fn checked_mul(self, other: Self) -> Option<Self> {
+        <$t>::checked_mul(self, other)
+      }
+      fn rem_euclid(self, other: Self) -> Self {
+        <$t>::rem_euclid(self, other)
+      }
+    }
+  )*};
+}
+impl_int!(i64, i128);
+
+// Returns (g, x, y) where g = gcd(a, b) is not negative and a*x + b*y = g.
+pub fn extended_gcd<T: Int>(a: T, b: T) -> (T, T, T) {
+  let (mut old_r, mut r) = (a, b);
+  let (mut old_x, mut x) = (T::ONE, T::ZERO);
+  let (mut old_y, mut y) = (T::ZERO, T::ONE);
+  while r != T::ZERO {
+    let q = old_r / r;
+    let next_r = old_r - q * r;
+    old_r = r;
+    r = next_r;
+    let next_x = old_x - q * x;
+    old_x = x;
+    x = next_x;
+    let next_y = old_y - q * y;
+    old_y = y;
+    y = next_y;
+  }
+  if old_r < T::ZERO {
+    (-old_r, -old_x, -old_y)
+  } else {
+    (old_r, old_x, old_y)
+  }
+}
+
+// The x in 0..m where a*x ≡ 1 (mod m), if a and m are coprime.
+pub fn mod_inverse<T: Int>(a: T, m: T) -> Option<T> {
+  let (g, x, _) = extended_gcd(a.rem_euclid(m), m);
+  if g == T::ONE {
+    Some(x.rem_euclid(m))
+  } else {
+    None
+  }
+}
+
+// Solves the system of congruences x ≡ residue (mod modulus), given as
+// (residue, modulus) pairs, using the Chinese Remainder Theorem. The moduli
+// don't need to be coprime. Returns (x, m) where x in 0..m is the smallest
+// solution and every solution is x plus a multiple of m, the lcm of the
+// moduli. Fails if the congruences contradict each other, or if the numbers
+// involved don't fit in `T`.
+pub fn crt<T: Int>(congruences: &[(T, T)]) -> anyhow::Result<(T, T)> {
+  let (mut x, mut m) = (T::ZERO, T::ONE);
+  for &(residue, modulus) in congruences {
+    if modulus <= T::ZERO {
+      return Err(anyhow!("modulus {} is not positive", modulus));
+    }
+    let residue = residue.rem_euclid(modulus);
+    // Solve x + m*k ≡ residue (mod modulus) for k. It has a solution only if
+    // the difference is a multiple of g, and then k is unique modulo
+    // modulus / g.
+    let (g, _, _) = extended_gcd(m, modulus);
+    let diff = residue - x.rem_euclid(modulus);
+    if diff % g != T::ZERO {
+      return Err(anyhow!(
+        "x ≡ {} (mod {}) contradicts the earlier congruences, which need x ≡ {} (mod {})",
+        residue,
+        modulus,
+        x,
+        m
+      ));
+    }
+    let reduced = modulus / g;
+    let inverse = mod_inverse(m / g, reduced).unwrap();
+    let overflow = || anyhow!("overflow combining x ≡ {} (mod {}) with x ≡ {} (mod {})", x, m, residue, modulus);
+    let k = (diff / g).rem_euclid(reduced).checked_mul(inverse).ok_or_else(overflow)?.rem_euclid(reduced);
+    let lcm = m.checked_mul(reduced).ok_or_else(overflow)?;
+    x = m.checked_mul(k).and_then(|mk| mk.checked_add(x)).ok_or_else(overflow)?.rem_euclid(lcm);
+    m = lcm;
+  }
+  Ok((x, m))
+}
@@ -1,235 +1,791 @@
-// #[macro_use]
+#[macro_use]
 extern crate anyhow;
 extern crate regex;
 
 use regex::Regex;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
 
 fn read_input() -> anyhow::Result<String> {
   Ok(std::fs::read_to_string("day14/input.txt")?)
   //Ok("mask = 000000000000000000000000000000X1001X\nmem[42] = 100\nmask = 00000000000000000000000000000000X0XX\nmem[26] = 1".to_owned())
 }
 
-struct Mask36 {
-  mask: [Option<bool>; 36],
+// The most bits that a bitmask, address or value can have.
+const MAX_WIDTH: u32 = 128;
+// The width of the puzzle's computer.
+const PUZZLE_WIDTH: u32 = 36;
+
+// All the bits within `width`.
+fn width_bits(width: u32) -> u128 {
+  if width >= MAX_WIDTH {
+    !0
+  } else {
+    (1 << width) - 1
+  }
 }
-impl Mask36 {
-  fn new() -> Mask36 {
-    Mask36 { mask: [None; 36] }
+
+// A mask of `width` bits, where each bit is forced to 1, forced to 0, or left
+// alone (X).
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Bitmask {
+  width: u32,
+  ones: u128,
+  zeros: u128,
+}
+impl Bitmask {
+  // A mask of all Xs.
+  fn new(width: u32) -> Bitmask {
+    assert!((1..=MAX_WIDTH).contains(&width));
+    Bitmask {
+      width,
+      ones: 0,
+      zeros: 0,
+    }
   }
 
-  fn from_str(s: &str) -> Mask36 {
-    assert_eq!(s.len(), 36);
-    Mask36 {
-      mask: {
-        let mut mask = [None; 36];
-        for (i, c) in s.chars().rev().enumerate() {
-          mask[i] = match c {
-            '0' => Some(false),
-            '1' => Some(true),
-            _ => None,
-          };
-        }
-        mask
-      },
+  // The bits which are X.
+  fn floating(&self) -> u128 {
+    width_bits(self.width) & !self.ones & !self.zeros
+  }
+
+  // The version 1 decoder masks values, overwriting the bits which aren't X.
+  fn masked_value(&self, value: u128) -> u128 {
+    (value | self.ones) & !self.zeros
+  }
+
+  // The version 2 decoder masks addresses, where 1s overwrite, 0s leave the
+  // address bit alone, and Xs float. The pattern stands for every address
+  // the floating bits expand to.
+  fn address_pattern(&self, addr: u128) -> AddressPattern {
+    let floating = self.floating();
+    AddressPattern {
+      fixed: (addr | self.ones) & !floating,
+      floating,
     }
   }
-  fn masked_value(&self, mut value: u36) -> u36 {
-    for i in 0..36 {
-      match self.mask[i] {
-        Some(bitvalue) => {
-          value.set_bit(i, bitvalue);
-        }
-        None => (),
+}
+impl std::str::FromStr for Bitmask {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> anyhow::Result<Bitmask> {
+    let width = s.len() as u32;
+    if !(1..=MAX_WIDTH).contains(&width) {
+      return Err(anyhow!(
+        "Mask `{}` has {} bits, expected 1 to {}",
+        s,
+        width,
+        MAX_WIDTH
+      ));
+    }
+    let mut mask = Bitmask::new(width);
+    for (i, c) in s.chars().rev().enumerate() {
+      match c {
+        '0' => mask.zeros |= 1 << i,
+        '1' => mask.ones |= 1 << i,
+        'X' => (),
+        _ => return Err(anyhow!("Mask `{}` has `{}`, expected 0, 1 or X", s, c)),
       }
     }
-    value
+    Ok(mask)
   }
+}
 
-  fn masked_addrs(&self, mut addr: u36) -> Vec<u36> {
-    for (i, m) in self.mask.iter().enumerate() {
-      if let Some(maskval) = m {
-        if *maskval {
-          addr.bits |= 1 << i;
-        }
-      }
+// Sparse memory, storing only the addresses which have been written.
+//
+// Sums of memory that don't fit in 128 bits are errors, which only matters
+// for very wide computers.
+trait Memory {
+  fn set(&mut self, addr: u128, value: u128);
+  // The written addresses and their values, in address order. Fails if the
+  // values can't be worked out without overflowing.
+  fn entries(&self) -> anyhow::Result<Vec<(u128, u128)>>;
+  fn stats(&self) -> MemoryStats;
+
+  // Writes `value` to every address in the pattern.
+  fn set_pattern(&mut self, pattern: AddressPattern, value: u128) {
+    for addr in pattern.addresses() {
+      self.set(addr, value);
     }
+  }
 
-    let x_indices: Vec<usize> = self
-      .mask
-      .iter()
-      .enumerate()
-      .filter(|(_i, x)| x.is_none())
-      .map(|(i, _none)| i)
-      .collect();
-    let addr_drop_xs_mask = {
-      let mut inverse = 0_u64;
-      for idx in &x_indices {
-        inverse |= 1 << *idx;
-      }
-      !inverse
-    };
-    // Base address, without any of the mask permutations.
-    addr.bits &= addr_drop_xs_mask;
-    // Find all permutations of the flipped bits indicated by `x_indices`.
-    fn permute(addrs: &mut Vec<u36>, mut x_indices: Vec<usize>, addr_bits: u64) {
-      if let Some(idx) = x_indices.pop() {
-        permute(addrs, x_indices.clone(), addr_bits);
-        let flipped_addr = addr_bits | 1 << idx;
-        addrs.push(u36::from_u64(flipped_addr));
-        permute(addrs, x_indices, flipped_addr);
-      }
+  fn sum(&self) -> anyhow::Result<u128> {
+    checked_sum(self.entries()?.iter().map(|(_addr, val)| *val))
+  }
+}
+
+fn checked_sum<I: Iterator<Item = u128>>(mut values: I) -> anyhow::Result<u128> {
+  values
+    .try_fold(0_u128, |sum, val| sum.checked_add(val))
+    .ok_or_else(|| anyhow!("Memory sum overflows 128 bits"))
+}
+
+#[derive(Debug, PartialEq)]
+struct MemoryStats {
+  // How many times memory was written.
+  writes: usize,
+  // How many entries the backend keeps, which are the different addresses
+  // written, or for symbolic memory the patterns of addresses.
+  cells: usize,
+  // Roughly how many bytes of heap the backend is using.
+  bytes: usize,
+}
+impl std::fmt::Display for MemoryStats {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(
+      f,
+      "{} writes to {} cells, about {} bytes",
+      self.writes, self.cells, self.bytes
+    )
+  }
+}
+
+// Each cell is an address and a value.
+const CELL_BYTES: usize = 2 * std::mem::size_of::<u128>();
+
+// Memory in a hash map, which writes in O(1) and sorts when listing entries.
+#[derive(Default)]
+struct HashMemory {
+  cells: HashMap<u128, u128>,
+  writes: usize,
+}
+impl Memory for HashMemory {
+  fn set(&mut self, addr: u128, value: u128) {
+    self.cells.insert(addr, value);
+    self.writes += 1;
+  }
+
+  fn entries(&self) -> anyhow::Result<Vec<(u128, u128)>> {
+    let mut entries = self.cells.iter().map(|(a, v)| (*a, *v)).collect::<Vec<_>>();
+    entries.sort_by_key(|(addr, _)| *addr);
+    Ok(entries)
+  }
+
+  fn stats(&self) -> MemoryStats {
+    MemoryStats {
+      writes: self.writes,
+      cells: self.cells.len(),
+      // A control byte per bucket along with its cell.
+      bytes: self.cells.capacity() * (CELL_BYTES + 1),
+    }
+  }
+
+  fn sum(&self) -> anyhow::Result<u128> {
+    checked_sum(self.cells.values().copied())
+  }
+}
+
+// Memory in a B-tree, which writes in O(log n) and is always in address
+// order.
+#[derive(Default)]
+struct BTreeMemory {
+  cells: BTreeMap<u128, u128>,
+  writes: usize,
+}
+impl Memory for BTreeMemory {
+  fn set(&mut self, addr: u128, value: u128) {
+    self.cells.insert(addr, value);
+    self.writes += 1;
+  }
+
+  fn entries(&self) -> anyhow::Result<Vec<(u128, u128)>> {
+    Ok(self.cells.iter().map(|(a, v)| (*a, *v)).collect())
+  }
+
+  fn stats(&self) -> MemoryStats {
+    // Nodes hold up to 11 cells, and are at least half full.
+    let nodes = self.cells.len().div_ceil(6);
+    MemoryStats {
+      writes: self.writes,
+      cells: self.cells.len(),
+      bytes: nodes * (11 * CELL_BYTES + 12 * std::mem::size_of::<usize>()),
     }
-    let mut addrs = Vec::<u36>::new();
-    addrs.push(addr);
-    permute(&mut addrs, x_indices, addr.bits);
-    addrs
   }
 }
 
-#[allow(non_camel_case_types)]
-#[derive(Clone, Copy)]
-struct u36 {
-  bits: u64,
+// A set of addresses given by some fixed bits, with every combination of the
+// floating bits. Floating bits are always 0 in `fixed`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct AddressPattern {
+  fixed: u128,
+  floating: u128,
 }
-impl u36 {
-  fn from_u64(input: u64) -> u36 {
-    u36 { bits: input }
+impl AddressPattern {
+  // The number of addresses, or None when all 128 bits float.
+  fn len(&self) -> Option<u128> {
+    1_u128.checked_shl(self.floating.count_ones())
+  }
+
+  // Every address in the pattern, by counting through the subsets of the
+  // floating bits.
+  fn addresses(&self) -> Vec<u128> {
+    let mut addrs = Vec::new();
+    let mut subset = 0_u128;
+    loop {
+      addrs.push(self.fixed | subset);
+      subset = subset.wrapping_sub(self.floating) & self.floating;
+      if subset == 0 {
+        return addrs;
+      }
+    }
   }
 
-  fn set_bit(&mut self, bitindex: usize, bitvalue: bool) {
-    assert!(bitindex < 36);
-    if bitvalue {
-      self.bits |= 1 << bitindex;
-    } else {
-      self.bits &= !(1 << bitindex);
+  // Shows the pattern like a mask, with X for the floating bits.
+  fn to_string(self, width: u32) -> String {
+    (0..width)
+      .rev()
+      .map(|i| match (self.floating >> i & 1, self.fixed >> i & 1) {
+        (1, _) => 'X',
+        (_, 1) => '1',
+        _ => '0',
+      })
+      .collect()
+  }
+
+  // The addresses in both patterns, if there are any.
+  fn intersection(&self, other: &AddressPattern) -> Option<AddressPattern> {
+    let fixed_in_both = !self.floating & !other.floating;
+    if (self.fixed ^ other.fixed) & fixed_in_both != 0 {
+      return None;
     }
+    Some(AddressPattern {
+      fixed: self.fixed | other.fixed,
+      floating: self.floating & other.floating,
+    })
   }
 }
 
-struct Array36 {
-  array: Vec<(u36, u36)>,
+// Memory for the version 2 decoder, which records each write to a pattern of
+// addresses without expanding the floating bits.
+//
+// The memory is kept as a signed sum of patterns, where each address holds
+// the sum of the coefficients of the patterns containing it. A write first
+// cancels out whatever the new pattern overlaps, by adding each overlap with
+// the opposite coefficient, which is inclusion-exclusion over the patterns
+// written so far. The cost of a write depends on how many patterns overlap,
+// never on how many floating bits they have.
+//
+// Coefficients are signed, and once one of them doesn't fit in an i128 the
+// memory can no longer be summed.
+#[derive(Default)]
+struct SymbolicMemory {
+  terms: HashMap<AddressPattern, i128>,
+  writes: usize,
+  overflowed: bool,
 }
-impl Array36 {
-  fn new() -> Array36 {
-    Array36 { array: vec![] }
+impl Memory for SymbolicMemory {
+  fn set(&mut self, addr: u128, value: u128) {
+    self.set_pattern(
+      AddressPattern {
+        fixed: addr,
+        floating: 0,
+      },
+      value,
+    );
+  }
+
+  // Expands every pattern, so this is only practical with few floating bits.
+  fn entries(&self) -> anyhow::Result<Vec<(u128, u128)>> {
+    if self.overflowed {
+      return Err(anyhow!("Memory coefficients overflow 128 bits"));
+    }
+    let mut cells = BTreeMap::<u128, i128>::new();
+    for (term, coefficient) in &self.terms {
+      for addr in term.addresses() {
+        let value = cells.entry(addr).or_insert(0);
+        *value = value
+          .checked_add(*coefficient)
+          .ok_or_else(|| anyhow!("Memory at {} overflows 128 bits", addr))?;
+      }
+    }
+    Ok(
+      cells
+        .into_iter()
+        .filter(|(_, v)| *v != 0)
+        .map(|(a, v)| (a, v as u128))
+        .collect(),
+    )
+  }
+
+  fn stats(&self) -> MemoryStats {
+    MemoryStats {
+      writes: self.writes,
+      cells: self.terms.len(),
+      bytes: self.terms.capacity() * (3 * std::mem::size_of::<u128>() + 1),
+    }
   }
 
-  fn set(&mut self, addr: u36, value: u36) {
-    for i in 0..self.array.len() {
-      if self.array[i].0.bits == addr.bits {
-        self.array[i].1 = value;
-        return;
-      } else if self.array[i].0.bits > addr.bits {
-        self.array.insert(i, (addr, value));
-        return;
+  fn set_pattern(&mut self, pattern: AddressPattern, value: u128) {
+    // None for an update that overflowed.
+    let mut updates = HashMap::<AddressPattern, Option<i128>>::new();
+    for (term, coefficient) in &self.terms {
+      if let Some(overlap) = term.intersection(&pattern) {
+        let update = updates.entry(overlap).or_insert(Some(0));
+        *update = update.and_then(|u| u.checked_sub(*coefficient));
       }
     }
-    self.array.push((addr, value));
+    let update = updates.entry(pattern).or_insert(Some(0));
+    *update = update.and_then(|u| u.checked_add(i128::try_from(value).ok()?));
+    for (term, change) in updates {
+      let coefficient = self.terms.entry(term).or_insert(0);
+      match change.and_then(|c| coefficient.checked_add(c)) {
+        Some(0) => {
+          self.terms.remove(&term);
+        }
+        Some(c) => *coefficient = c,
+        None => self.overflowed = true,
+      }
+    }
+    self.writes += 1;
   }
-  fn set_group(&mut self, addrs: &Vec<u36>, value: u36) {
-    for addr in addrs {
-      self.set(*addr, value);
+
+  // The positive and negative terms are added up separately, so this fails
+  // if the positive ones overflow even when the whole sum would fit.
+  fn sum(&self) -> anyhow::Result<u128> {
+    if self.overflowed {
+      return Err(anyhow!("Memory coefficients overflow 128 bits"));
     }
+    let mut added = 0_u128;
+    let mut removed = 0_u128;
+    for (term, coefficient) in &self.terms {
+      let side = if *coefficient > 0 {
+        &mut added
+      } else {
+        &mut removed
+      };
+      *side = term
+        .len()
+        .and_then(|len| len.checked_mul(coefficient.unsigned_abs()))
+        .and_then(|total| side.checked_add(total))
+        .ok_or_else(|| anyhow!("Memory sum overflows 128 bits"))?;
+    }
+    Ok(added - removed)
   }
+}
 
-  fn sum(&self) -> u64 {
-    self.array.iter().map(|(_i, val)| val.bits).sum()
+fn new_memory(backend: &str) -> anyhow::Result<Box<dyn Memory>> {
+  match backend {
+    "hash" => Ok(Box::new(HashMemory::default())),
+    "btree" => Ok(Box::new(BTreeMemory::default())),
+    "symbolic" => Ok(Box::new(SymbolicMemory::default())),
+    _ => Err(anyhow!(
+      "Unknown memory backend `{}`, expected hash, btree or symbolic",
+      backend
+    )),
   }
 }
 
 enum Instruction {
-  UpdateMask(Mask36),
-  SetMem((u36, u36)),
+  UpdateMask(Bitmask),
+  SetMem((u128, u128)),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Decoder {
+  // Masks the values written.
+  Version1,
+  // Masks the addresses written to.
+  Version2,
+}
+impl std::str::FromStr for Decoder {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> anyhow::Result<Decoder> {
+    match s {
+      "v1" => Ok(Decoder::Version1),
+      "v2" => Ok(Decoder::Version2),
+      _ => Err(anyhow!("Unknown decoder `{}`, expected v1 or v2", s)),
+    }
+  }
+}
+
+// A single `mem[]` write, as the decoder saw it.
+struct TraceStep {
+  mask: Bitmask,
+  addr: u128,
+  raw: u128,
+  // The addresses written, and the value written to them.
+  written: AddressPattern,
+  value: u128,
+}
+impl std::fmt::Display for TraceStep {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(
+      f,
+      "mask {}  mem[{}] = {}  ->  mem[{}] = {}",
+      self.mask,
+      self.addr,
+      self.raw,
+      self.written.to_string(self.mask.width),
+      self.value
+    )
+  }
+}
+
+// Runs a docking program on a computer of any width up to 128 bits.
+struct Emulator<'a> {
+  decoder: Decoder,
+  mask: Bitmask,
+  memory: &'a mut dyn Memory,
+  // Each write so far, when tracing.
+  trace: Option<Vec<TraceStep>>,
+}
+impl<'a> Emulator<'a> {
+  fn new(width: u32, decoder: Decoder, memory: &'a mut dyn Memory) -> Self {
+    Emulator {
+      decoder,
+      mask: Bitmask::new(width),
+      memory,
+      trace: None,
+    }
+  }
+
+  fn with_trace(mut self) -> Self {
+    self.trace = Some(Vec::new());
+    self
+  }
+
+  fn width(&self) -> u32 {
+    self.mask.width
+  }
+
+  fn execute(&mut self, inst: &Instruction) -> anyhow::Result<()> {
+    match *inst {
+      Instruction::UpdateMask(mask) => {
+        if mask.width != self.width() {
+          return Err(anyhow!(
+            "Mask {} has {} bits on a {} bit computer",
+            mask,
+            mask.width,
+            self.width()
+          ));
+        }
+        self.mask = mask;
+      }
+      Instruction::SetMem((addr, raw)) => {
+        for n in &[addr, raw] {
+          if n & !width_bits(self.width()) != 0 {
+            return Err(anyhow!("{} does not fit in {} bits", n, self.width()));
+          }
+        }
+        let (written, value) = match self.decoder {
+          Decoder::Version1 => (
+            AddressPattern {
+              fixed: addr,
+              floating: 0,
+            },
+            self.mask.masked_value(raw),
+          ),
+          Decoder::Version2 => (self.mask.address_pattern(addr), raw),
+        };
+        self.memory.set_pattern(written, value);
+        if let Some(trace) = &mut self.trace {
+          trace.push(TraceStep {
+            mask: self.mask,
+            addr,
+            raw,
+            written,
+            value,
+          });
+        }
+      }
+    }
+    Ok(())
+  }
+
+  fn run(&mut self, instructions: &[Instruction]) -> anyhow::Result<u128> {
+    for inst in instructions {
+      self.execute(inst)?;
+    }
+    self.memory.sum()
+  }
 }
 
 fn main() -> anyhow::Result<()> {
+  let args = std::env::args().skip(1).collect::<Vec<_>>();
+
+  // `day14 trace v1|v2 [WIDTH] [FILE]` runs a program with either decoder,
+  // printing each write.
+  if args.first().map(|s| s.as_str()) == Some("trace") {
+    let decoder = args
+      .get(1)
+      .ok_or(anyhow!("trace needs a decoder"))?
+      .parse()?;
+    let (width, file_arg) = match args.get(2).map(|s| s.parse::<u32>()) {
+      Some(Ok(width)) => (width, args.get(3)),
+      _ => (PUZZLE_WIDTH, args.get(2)),
+    };
+    if !(1..=MAX_WIDTH).contains(&width) {
+      return Err(anyhow!("Width must be 1 to {}", MAX_WIDTH));
+    }
+    let in_str = match file_arg {
+      Some(file) => std::fs::read_to_string(file)?,
+      None => read_input()?,
+    };
+    let lines: Vec<&str> = in_str.split_terminator("\n").collect();
+    let instructions = parse_instructions(&lines)?;
+    let mut memory = SymbolicMemory::default();
+    let mut emulator = Emulator::new(width, decoder, &mut memory).with_trace();
+    let sum = emulator.run(&instructions)?;
+    for step in emulator.trace.iter().flatten() {
+      println!("{}", step);
+    }
+    println!("Sum {}", sum);
+    return Ok(());
+  }
+
+  // `day14 [hash|btree|symbolic] [stats]` picks the memory backend, and
+  // prints how much it used. By default, part 1 uses hash memory and part 2
+  // uses symbolic memory, which doesn't expand floating addresses.
+  let chosen_backend = args.iter().find(|a| *a != "stats").map(|a| a.as_str());
+  let show_stats = args.iter().any(|a| a == "stats");
+
   let in_str = read_input()?;
-  p1(&in_str)?;
-  p2(&in_str)?;
+  let mut memory = new_memory(chosen_backend.unwrap_or("hash"))?;
+  println!("Part 1 {}", p1(&in_str, memory.as_mut())?);
+  if show_stats {
+    println!("  {}", memory.stats());
+  }
+  let mut memory = new_memory(chosen_backend.unwrap_or("symbolic"))?;
+  println!("Part 2 {}", p2(&in_str, memory.as_mut())?);
+  if show_stats {
+    println!("  {}", memory.stats());
+  }
   Ok(())
 }
 
-fn parse_instructions(lines: &Vec<&str>) -> anyhow::Result<Vec<Instruction>> {
+fn parse_instructions(lines: &[&str]) -> anyhow::Result<Vec<Instruction>> {
   let mut instructions = Vec::<Instruction>::new();
 
-  let mask_re = Regex::new(r"^mask = ([X01]{36})$").unwrap();
+  let mask_re = Regex::new(r"^mask = ([X01]+)$").unwrap();
   let memset_re = Regex::new(r"^mem\[([0-9]+)\] = ([0-9]+)$").unwrap();
   for line in lines {
     if let Some(mask_cap) = mask_re.captures(line) {
-      let mask = Mask36::from_str(&mask_cap[1]);
+      let mask = mask_cap[1].parse()?;
       instructions.push(Instruction::UpdateMask(mask));
     } else if let Some(memset_cap) = memset_re.captures(line) {
-      let addr = u36::from_u64(memset_cap[1].parse()?);
-      let val = u36::from_u64(memset_cap[2].parse()?);
+      let addr = memset_cap[1].parse()?;
+      let val = memset_cap[2].parse()?;
       instructions.push(Instruction::SetMem((addr, val)));
     }
   }
   Ok(instructions)
 }
 
-fn p1(in_str: &String) -> anyhow::Result<()> {
+fn p1(in_str: &str, memory: &mut dyn Memory) -> anyhow::Result<u128> {
   let lines: Vec<&str> = in_str.split_terminator("\n").collect();
   let instructions = parse_instructions(&lines)?;
-
-  let mut cur_mask = Mask36::new();
-  let mut memory = Array36::new();
-  for inst in instructions {
-    match inst {
-      Instruction::UpdateMask(mask) => cur_mask = mask,
-      Instruction::SetMem((addr, value)) => memory.set(addr, cur_mask.masked_value(value)),
-    }
-  }
-
-  println!("Part 1 {}", memory.sum());
-  Ok(())
+  Emulator::new(PUZZLE_WIDTH, Decoder::Version1, memory).run(&instructions)
 }
 
-fn p2(in_str: &String) -> anyhow::Result<()> {
+// Symbolic memory solves this without expanding floating addresses, and
+// other backends expand them into every address.
+fn p2(in_str: &str, memory: &mut dyn Memory) -> anyhow::Result<u128> {
   let lines: Vec<&str> = in_str.split_terminator("\n").collect();
   let instructions = parse_instructions(&lines)?;
-
-  let mut cur_mask = Mask36::new();
-  let mut memory = Array36::new();
-  for inst in instructions {
-    match inst {
-      Instruction::UpdateMask(mask) => cur_mask = mask,
-      Instruction::SetMem((addr, value)) => memory.set_group(&cur_mask.masked_addrs(addr), value),
-    }
-  }
-
-  println!("Part 2 {}", memory.sum());
-  Ok(())
+  Emulator::new(PUZZLE_WIDTH, Decoder::Version2, memory).run(&instructions)
 }
 
-impl std::fmt::Display for Mask36 {
+impl std::fmt::Display for Bitmask {
   fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
     let mut s = "".to_owned();
-    for bit in self.mask.iter().rev() {
-      s += match bit {
-        Some(b) => match b {
-          true => "1",
-          false => "0",
-        },
-        None => "X",
+    for i in (0..self.width).rev() {
+      s += match (self.ones >> i & 1, self.zeros >> i & 1) {
+        (1, _) => "1",
+        (_, 1) => "0",
+        _ => "X",
       }
     }
     write!(f, "{}", s)?;
     Ok(())
   }
 }
-impl std::fmt::Display for u36 {
-  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-    write!(f, "{}", self.bits)?;
-    Ok(())
-  }
-}
-impl std::fmt::Display for Array36 {
+impl std::fmt::Display for dyn Memory {
   fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
     let mut s = "".to_owned();
-    for (i, val) in self.array.iter() {
+    for (i, val) in self.entries().map_err(|_| std::fmt::Error)?.iter() {
       s += &format!("[{}, {}] ", i, val);
     }
     write!(f, "{}", s)?;
     Ok(())
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const TEST_INPUT_1: &str = "mask = XXXXXXXXXXXXXXXXXXXXXXXXXXXXX1XXXX0X
+mem[8] = 11
+mem[7] = 101
+mem[8] = 0
+";
+  const TEST_INPUT_2: &str = "mask = 000000000000000000000000000000X1001X
+mem[42] = 100
+mask = 00000000000000000000000000000000X0XX
+mem[26] = 1
+";
+
+  #[test]
+  fn test_example() -> anyhow::Result<()> {
+    for backend in &["hash", "btree", "symbolic"] {
+      assert_eq!(p1(TEST_INPUT_1, new_memory(backend)?.as_mut())?, 165);
+      assert_eq!(p2(TEST_INPUT_2, new_memory(backend)?.as_mut())?, 208);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_symbolic_memory() -> anyhow::Result<()> {
+    // Programs with overlapping floating addresses, from a fixed seed.
+    let mut seed = 14_u64;
+    let mut next = |n: u64| {
+      seed = seed
+        .wrapping_mul(6364136223846793005)
+        .wrapping_add(1442695040888963407);
+      (seed >> 33) % n
+    };
+    for _ in 0..20 {
+      let mut program = String::new();
+      for _ in 0..10 {
+        let mask = (0..36)
+          .map(|i| match (i, next(4)) {
+            (0..=29, _) => '0',
+            (_, 0) => '0',
+            (_, 1) => '1',
+            _ => 'X',
+          })
+          .collect::<String>();
+        program += &format!("mask = {}\n", mask);
+        for _ in 0..3 {
+          program += &format!("mem[{}] = {}\n", next(64), next(1000));
+        }
+      }
+      let expanded = p2(&program, &mut HashMemory::default())?;
+      assert_eq!(p2(&program, &mut SymbolicMemory::default())?, expanded);
+    }
+
+    // Too many floating bits to expand.
+    let program = "mask = 1XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX0
+mem[0] = 3
+mask = 1XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX
+mem[0] = 2
+mask = 10000000000000000000000000000000000X
+mem[0] = 1
+";
+    assert_eq!(
+      p2(program, &mut SymbolicMemory::default())?,
+      2 * (1 << 35) - 2
+    );
+
+    // Every address of a 128 bit computer, whose sum overflows.
+    let program = format!("mask = {}\nmem[0] = 1\n", "X".repeat(128));
+    let mut memory = SymbolicMemory::default();
+    let lines: Vec<&str> = program.lines().collect();
+    let mut emulator = Emulator::new(128, Decoder::Version2, &mut memory);
+    assert!(emulator.run(&parse_instructions(&lines)?).is_err());
+
+    // Half of them, which only just fits.
+    let program = format!("mask = 0{}\nmem[0] = 1\n", "X".repeat(127));
+    let lines: Vec<&str> = program.lines().collect();
+    let mut memory = SymbolicMemory::default();
+    let mut emulator = Emulator::new(128, Decoder::Version2, &mut memory);
+    assert_eq!(emulator.run(&parse_instructions(&lines)?)?, 1 << 127);
+
+    // A value too big for a signed coefficient.
+    let mut memory = SymbolicMemory::default();
+    memory.set(0, u128::MAX);
+    assert!(memory.sum().is_err());
+    assert!(memory.entries().is_err());
+
+    // Coefficients which overflow when added up for one address.
+    let mut memory = SymbolicMemory::default();
+    let pattern = |floating| AddressPattern { fixed: 0, floating };
+    memory.terms.insert(pattern(1), i128::MAX);
+    memory.terms.insert(pattern(0), 1);
+    assert!(memory.entries().is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_memory() -> anyhow::Result<()> {
+    for backend in &["hash", "btree", "symbolic"] {
+      let mut memory = new_memory(backend)?;
+      memory.set(9, 1);
+      memory.set(3, 2);
+      memory.set(9, 4);
+      assert_eq!(memory.entries()?, vec![(3, 2), (9, 4)]);
+      assert_eq!(memory.to_string(), "[3, 2] [9, 4] ");
+      assert_eq!(memory.sum()?, 6);
+      let stats = memory.stats();
+      assert_eq!(stats.writes, 3);
+      // Symbolic memory counts patterns rather than addresses.
+      if *backend != "symbolic" {
+        assert_eq!(stats.cells, 2);
+      }
+    }
+
+    let mut memory = HashMemory::default();
+    memory.set(0, u128::MAX);
+    memory.set(1, 1);
+    assert!(memory.sum().is_err());
+    assert!(new_memory("array").is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_emulator() -> anyhow::Result<()> {
+    let mask: Bitmask = "X1X0".parse()?;
+    assert_eq!(mask.to_string(), "X1X0");
+    assert_eq!(mask.masked_value(0b1011), 0b1110);
+    assert_eq!(mask.address_pattern(0b0011).to_string(4), "X1X1");
+    assert_eq!(
+      mask.address_pattern(0b0011).addresses(),
+      vec![0b0101, 0b0111, 0b1101, 0b1111]
+    );
+    assert!("".parse::<Bitmask>().is_err());
+    assert!("X1Y0".parse::<Bitmask>().is_err());
+    assert!("X".repeat(129).parse::<Bitmask>().is_err());
+    let wide: Bitmask = format!("1{}", "X".repeat(127)).parse()?;
+    assert_eq!(wide.masked_value(0), 1 << 127);
+
+    let program = "mask = X1X0\nmem[3] = 9\nmem[1] = 6\n";
+    let lines: Vec<&str> = program.lines().collect();
+    let instructions = parse_instructions(&lines)?;
+    let mut memory = BTreeMemory::default();
+    let mut emulator = Emulator::new(4, Decoder::Version1, &mut memory).with_trace();
+    assert_eq!(emulator.run(&instructions)?, 12 + 6);
+    let trace = emulator
+      .trace
+      .iter()
+      .flatten()
+      .map(|s| s.to_string())
+      .collect::<Vec<_>>();
+    assert_eq!(
+      trace,
+      vec![
+        "mask X1X0  mem[3] = 9  ->  mem[0011] = 12",
+        "mask X1X0  mem[1] = 6  ->  mem[0001] = 6"
+      ]
+    );
+
+    let mut memory = BTreeMemory::default();
+    let mut emulator = Emulator::new(4, Decoder::Version2, &mut memory).with_trace();
+    assert_eq!(emulator.run(&instructions)?, 4 * 6);
+    assert_eq!(
+      emulator.trace.unwrap()[0].to_string(),
+      "mask X1X0  mem[3] = 9  ->  mem[X1X1] = 9"
+    );
+    // Both addresses mask to the same pattern, so the second write replaces
+    // the first.
+    assert_eq!(memory.entries()?, vec![(5, 6), (7, 6), (13, 6), (15, 6)]);
+
+    // The mask and numbers must fit the computer.
+    let mut memory = HashMemory::default();
+    let mut emulator = Emulator::new(3, Decoder::Version1, &mut memory);
+    assert!(emulator.run(&instructions).is_err());
+    let mut emulator = Emulator::new(3, Decoder::Version1, &mut memory);
+    assert!(emulator.execute(&Instruction::SetMem((8, 0))).is_err());
+    Ok(())
+  }
+}
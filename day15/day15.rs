@@ -1,71 +1,535 @@
-// #[macro_use]
+#[macro_use]
 extern crate anyhow;
-//extern crate regex;
-//use regex::Regex;
+use std::collections::HashMap;
+use std::io::{Read, Write};
 
-fn read_input() -> anyhow::Result<String> {
-  //Ok(std::fs::read_to_string("day15/input.txt")?)
-  Ok("0,20,7,16,1,18,15".to_owned())
+static INPUT_FILE: &str = "day15/input.txt";
+
+// Numbers below this have their turns kept in a flat table, which grows as
+// larger numbers are spoken. Numbers above it are rare enough to go in a
+// hash map instead. Playing a game up to a given turn raises the limit to
+// that turn, so every number spoken after the starting numbers is in the
+// table.
+const FLAT_LIMIT: u64 = 1 << 28;
+
+// A turn in the flat table which means the number's turn is too large for a
+// u32, and is in the hash map instead.
+const TURN_IN_MAP: u32 = u32::MAX;
+
+// The start of a checkpoint file, which is followed by the game's state in
+// little-endian.
+const CHECKPOINT_MAGIC: &[u8] = b"day15 memory game v2\n";
+
+// How many turns to play between checkpoints.
+const CHECKPOINT_INTERVAL: u64 = 100_000_000;
+
+// Reads the starting numbers from the command line, as either the numbers
+// themselves or a file holding them. Without either, reads the puzzle input.
+fn read_input(arg: Option<&String>) -> anyhow::Result<String> {
+  match arg {
+    Some(arg) if arg.chars().all(|c| c.is_ascii_digit() || c == ',') => Ok(arg.clone()),
+    Some(file) => Ok(std::fs::read_to_string(file)?),
+    None => Ok(std::fs::read_to_string(INPUT_FILE)?),
+  }
 }
 
-enum SpokenTurns {
-  Once(/*turn=*/u64),
-  Many(/*last_turn=*/u64, /*before_turn=*/u64),
+fn parse_starting_numbers(input_all: &str) -> anyhow::Result<Vec<u64>> {
+  let nums = input_all
+    .trim()
+    .split(',')
+    .map(|s| s.trim().parse::<u64>().map_err(|e| anyhow!("Bad starting number `{}`: {}", s, e)))
+    .collect::<anyhow::Result<Vec<u64>>>()?;
+  if nums.is_empty() {
+    return Err(anyhow!("No starting numbers"));
+  }
+  Ok(nums)
+}
+
+// The elves' memory game (the Van Eck sequence): after the starting numbers,
+// each turn speaks 0 if the previous number was new, and otherwise how many
+// turns apart its last two mentions were.
+//
+// Turns are counted in a u64, so a game can run for up to u64::MAX turns,
+// after which the iterator ends.
+//
+// Along the way, the game keeps some statistics about the numbers spoken so
+// far, and can be saved to a checkpoint file and resumed.
+struct MemoryGame {
+  starting: Vec<u64>,
+  // The turn each number was last spoken on, by number, or 0 if it hasn't
+  // been spoken. Turns start at 1.
+  last_seen: Vec<u32>,
+  // The same, for numbers from `flat_limit` up, and for numbers whose turn
+  // is TURN_IN_MAP in `last_seen`.
+  last_seen_large: HashMap<u64, u64>,
+  // The numbers below this are kept in `last_seen`. It starts at FLAT_LIMIT.
+  flat_limit: u64,
+  // How many turns have been played.
+  turn: u64,
+  // The number to speak next, once the starting numbers are done.
+  next_number: u64,
+  // The number spoken on the last turn.
+  last_spoken: Option<u64>,
+  // How many different numbers have been spoken.
+  distinct: u64,
+  // The most turns between two mentions of the same number.
+  largest_gap: Option<Gap>,
 }
 
-struct Part1 {
-  history: std::collections::BTreeMap</*spoken_number=*/u64, SpokenTurns>,
-  last_spoken: u64,
-  current_turn: u64,
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Gap {
+  number: u64,
+  // How many turns apart the mentions were.
+  length: u64,
+  // The turn of the later mention.
+  turn: u64,
 }
-impl Part1 {
-  fn new() -> Part1 {
-    Part1 {
-      history: std::collections::BTreeMap::new(),
-      last_spoken: 0,
-      current_turn: 1,
+impl MemoryGame {
+  fn new(starting: &[u64]) -> MemoryGame {
+    MemoryGame {
+      starting: starting.to_vec(),
+      last_seen: Vec::new(),
+      last_seen_large: HashMap::new(),
+      flat_limit: FLAT_LIMIT,
+      turn: 0,
+      next_number: 0,
+      last_spoken: None,
+      distinct: 0,
+      largest_gap: None,
     }
   }
 
-  fn speak(&mut self, spoken_number: u64) {
-    let spoken_turns = if self.history.contains_key(&spoken_number) {
-      match self.history[&spoken_number] {
-        SpokenTurns::Once(last_turn) => SpokenTurns::Many(self.current_turn, last_turn),
-        SpokenTurns::Many(last_turn, _) => SpokenTurns::Many(self.current_turn, last_turn),
+  // Prepares for a game of `turns` turns. No number spoken after the
+  // starting numbers can be as large as the turn it's spoken on, so above
+  // FLAT_LIMIT this raises the game's limit to keep them all in the table,
+  // moving any numbers now below it out of the hash map. The table itself
+  // still only grows as larger numbers are spoken.
+  fn raise_flat_limit(&mut self, turns: u64) {
+    if turns > self.flat_limit {
+      self.flat_limit = turns;
+      let moved = self.last_seen_large.keys().filter(|&&n| n < turns).copied().collect::<Vec<_>>();
+      if let Some(&largest) = moved.iter().max() {
+        self.last_seen.resize(self.last_seen.len().max(largest as usize + 1), 0);
       }
-    } else {
-      SpokenTurns::Once(self.current_turn)
+      for n in moved {
+        let turn = self.last_seen_large[&n];
+        if turn < TURN_IN_MAP as u64 {
+          self.last_seen[n as usize] = turn as u32;
+          self.last_seen_large.remove(&n);
+        } else {
+          self.last_seen[n as usize] = TURN_IN_MAP;
+        }
+      }
+    }
+  }
+
+  // Records that `number` was spoken on `turn`, returning the turn it was
+  // spoken on before, or 0 if it's new.
+  fn remember(&mut self, number: u64, turn: u64) -> u64 {
+    if number >= self.flat_limit {
+      return self.last_seen_large.insert(number, turn).unwrap_or(0);
+    }
+    let index = number as usize;
+    if index >= self.last_seen.len() {
+      let len = (index + 1).max(self.last_seen.len() * 2).min(self.flat_limit as usize);
+      self.last_seen.resize(len, 0);
+    }
+    let slot = &mut self.last_seen[index];
+    let previous = *slot;
+    if turn < TURN_IN_MAP as u64 {
+      *slot = turn as u32;
+      return previous as u64;
+    }
+    *slot = TURN_IN_MAP;
+    // Only None when the number's previous turn was in the table.
+    self.last_seen_large.insert(number, turn).unwrap_or(previous as u64)
+  }
+
+  // Plays until `turn` has been played, returning the number spoken on it.
+  fn play_until(&mut self, turn: u64) -> anyhow::Result<u64> {
+    if turn == 0 {
+      return Err(anyhow!("There is no turn 0, turn numbers start at 1"));
+    }
+    if turn < self.turn {
+      return Err(anyhow!("Turn {} has passed, the game is at turn {}", turn, self.turn));
+    }
+    self.raise_flat_limit(turn);
+    for _ in self.turn..turn {
+      self.next();
+    }
+    Ok(self.last_spoken.unwrap())
+  }
+
+  // The first turn that `number` is spoken on, looking as far as turn
+  // `limit`.
+  fn first_spoken(starting: &[u64], number: u64, limit: u64) -> Option<u64> {
+    let mut game = MemoryGame::new(starting);
+    game.raise_flat_limit(limit);
+    let position = game.take(limit as usize).position(|n| n == number)?;
+    Some(position as u64 + 1)
+  }
+
+  // Saves the game to `path`. The file is written beside it first, so a
+  // checkpoint from before is kept if this is interrupted.
+  fn save(&self, path: &str) -> anyhow::Result<()> {
+    let partial_path = format!("{}.partial", path);
+    let mut w = std::io::BufWriter::new(std::fs::File::create(&partial_path)?);
+    w.write_all(CHECKPOINT_MAGIC)?;
+    w.write_all(&(self.starting.len() as u64).to_le_bytes())?;
+    for n in &self.starting {
+      w.write_all(&n.to_le_bytes())?;
+    }
+    w.write_all(&self.turn.to_le_bytes())?;
+    w.write_all(&self.flat_limit.to_le_bytes())?;
+    w.write_all(&self.next_number.to_le_bytes())?;
+    w.write_all(&self.last_spoken.unwrap_or(0).to_le_bytes())?;
+    w.write_all(&self.distinct.to_le_bytes())?;
+    let gap = self.largest_gap.unwrap_or(Gap {
+      number: 0,
+      length: 0,
+      turn: 0,
+    });
+    w.write_all(&gap.number.to_le_bytes())?;
+    w.write_all(&gap.length.to_le_bytes())?;
+    w.write_all(&gap.turn.to_le_bytes())?;
+    // The end of the table is often unused.
+    let table_len = self.last_seen.iter().rposition(|&t| t != 0).map_or(0, |i| i + 1);
+    w.write_all(&(table_len as u64).to_le_bytes())?;
+    for t in &self.last_seen[..table_len] {
+      w.write_all(&t.to_le_bytes())?;
+    }
+    w.write_all(&(self.last_seen_large.len() as u64).to_le_bytes())?;
+    for (n, t) in &self.last_seen_large {
+      w.write_all(&n.to_le_bytes())?;
+      w.write_all(&t.to_le_bytes())?;
+    }
+    w.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    std::fs::rename(&partial_path, path)?;
+    Ok(())
+  }
+
+  fn load(path: &str) -> anyhow::Result<MemoryGame> {
+    let mut r = std::io::BufReader::new(std::fs::File::open(path)?);
+    let mut magic = [0u8; CHECKPOINT_MAGIC.len()];
+    r.read_exact(&mut magic)?;
+    if magic != CHECKPOINT_MAGIC {
+      return Err(anyhow!("{} is not a memory game checkpoint", path));
+    }
+    fn read_u64<R: Read>(r: &mut R) -> anyhow::Result<u64> {
+      let mut bytes = [0u8; 8];
+      r.read_exact(&mut bytes)?;
+      Ok(u64::from_le_bytes(bytes))
+    }
+    fn read_u32<R: Read>(r: &mut R) -> anyhow::Result<u32> {
+      let mut bytes = [0u8; 4];
+      r.read_exact(&mut bytes)?;
+      Ok(u32::from_le_bytes(bytes))
+    }
+
+    let starting = (0..read_u64(&mut r)?)
+      .map(|_| read_u64(&mut r))
+      .collect::<anyhow::Result<Vec<_>>>()?;
+    let mut game = MemoryGame::new(&starting);
+    game.turn = read_u64(&mut r)?;
+    game.flat_limit = read_u64(&mut r)?;
+    game.next_number = read_u64(&mut r)?;
+    let last_spoken = read_u64(&mut r)?;
+    game.last_spoken = if game.turn > 0 { Some(last_spoken) } else { None };
+    game.distinct = read_u64(&mut r)?;
+    let gap = Gap {
+      number: read_u64(&mut r)?,
+      length: read_u64(&mut r)?,
+      turn: read_u64(&mut r)?,
     };
-    self.history.insert(spoken_number, spoken_turns);
-    self.last_spoken = spoken_number;
-    self.current_turn += 1;
+    game.largest_gap = if gap.length > 0 { Some(gap) } else { None };
+    let table_len = read_u64(&mut r)?;
+    // The limit is only raised to a turn that is then played.
+    if table_len > game.flat_limit || game.flat_limit > FLAT_LIMIT.max(game.turn) {
+      return Err(anyhow!("{} has a table too large for this game", path));
+    }
+    game.last_seen = (0..table_len).map(|_| read_u32(&mut r)).collect::<anyhow::Result<Vec<_>>>()?;
+    for _ in 0..read_u64(&mut r)? {
+      let n = read_u64(&mut r)?;
+      game.last_seen_large.insert(n, read_u64(&mut r)?);
+    }
+    if r.read(&mut [0u8])? != 0 {
+      return Err(anyhow!("{} has data past the end of the checkpoint", path));
+    }
+    Ok(game)
+  }
+
+  // The number spoken on `turn`, counting from 1.
+  fn nth_spoken(starting: &[u64], turn: u64) -> Option<u64> {
+    let mut game = MemoryGame::new(starting);
+    game.raise_flat_limit(turn);
+    game.nth(turn.checked_sub(1)? as usize)
   }
 }
+impl Iterator for MemoryGame {
+  type Item = u64;
 
-fn run(part: u32, input_all: &str, num_turns_to_run: usize) -> anyhow::Result<()> {
-  let split_input = input_all.split_terminator(",");
-  let nums = split_input.map(|s: &str| s.parse::<u64>().unwrap()).collect::<Vec<u64>>();
-  
-  let mut state = Part1::new();
-  // Bootstrap from `nums`.
-  for spoken_num in &nums {
-    state.speak(*spoken_num);
-  }
-  for _ in 0..(num_turns_to_run-nums.len()) {
-    let last_spoken = &state.history[&state.last_spoken];
-    let next_spoken_number = match last_spoken {
-      SpokenTurns::Once(_) => 0,
-      SpokenTurns::Many(a, b) => a - b,
+  fn next(&mut self) -> Option<u64> {
+    if self.turn == u64::MAX {
+      return None;
+    }
+    let number = match self.starting.get(self.turn as usize) {
+      Some(&n) => n,
+      None => self.next_number,
     };
-    state.speak(next_spoken_number);
+    self.turn += 1;
+    let previous = self.remember(number, self.turn);
+    self.next_number = match previous {
+      0 => {
+        self.distinct += 1;
+        0
+      }
+      previous => {
+        let length = self.turn - previous;
+        if self.largest_gap.is_none_or(|gap| length > gap.length) {
+          self.largest_gap = Some(Gap {
+            number,
+            length,
+            turn: self.turn,
+          });
+        }
+        length
+      }
+    };
+    self.last_spoken = Some(number);
+    Some(number)
   }
-  println!("Part {} {}", part, state.last_spoken);
+}
+
+fn run(part: u32, nums: &[u64], num_turns_to_run: u64) -> anyhow::Result<()> {
+  let spoken = MemoryGame::nth_spoken(nums, num_turns_to_run).ok_or(anyhow!("No turn {}", num_turns_to_run))?;
+  println!("Part {} {}", part, spoken);
   Ok(())
 }
 
+// Plays until `turn`, resuming from the checkpoint at `path` if there is one,
+// and saving a checkpoint every CHECKPOINT_INTERVAL turns and at the end.
+fn run_with_checkpoints(path: &str, nums: &[u64], turn: u64) -> anyhow::Result<MemoryGame> {
+  let mut game = if std::path::Path::new(path).exists() {
+    let game = MemoryGame::load(path)?;
+    if game.starting != nums {
+      return Err(anyhow!("{} is a game with other starting numbers", path));
+    }
+    println!("Resuming from turn {}", game.turn);
+    game
+  } else {
+    MemoryGame::new(nums)
+  };
+  while game.turn < turn {
+    let stop = turn.min(game.turn.saturating_add(CHECKPOINT_INTERVAL));
+    game.play_until(stop)?;
+    game.save(path)?;
+    println!("Saved at turn {}", game.turn);
+  }
+  Ok(game)
+}
+
+fn print_stats(game: &MemoryGame) {
+  println!("Turn {} {}", game.turn, game.last_spoken.unwrap_or(0));
+  println!("Distinct numbers {}", game.distinct);
+  if let Some(gap) = game.largest_gap {
+    println!(
+      "Largest gap {} turns, between mentions of {} ending on turn {}",
+      gap.length, gap.number, gap.turn
+    );
+  }
+}
+
 fn main() -> anyhow::Result<()> {
-  let input_all = read_input()?;
-  run(1, &input_all, 2020)?;
-  run(2, &input_all, 30000000)?;
+  let args = std::env::args().skip(1).collect::<Vec<_>>();
+  let turn_arg = |i: usize| -> anyhow::Result<u64> {
+    let turn = args.get(i).ok_or(anyhow!("Expected a turn"))?;
+    turn.parse::<u64>().map_err(|e| anyhow!("Bad turn `{}`: {}", turn, e))
+  };
+  match args.first().map(|s| s.as_str()) {
+    // `day15 first NUMBER LIMIT [NUMBERS|FILE]` finds the first turn that
+    // NUMBER is spoken on.
+    Some("first") => {
+      let number = args.get(1).ok_or(anyhow!("Expected a number"))?.parse()?;
+      let limit = turn_arg(2)?;
+      let nums = parse_starting_numbers(&read_input(args.get(3))?)?;
+      match MemoryGame::first_spoken(&nums, number, limit) {
+        Some(turn) => println!("{} is first spoken on turn {}", number, turn),
+        None => println!("{} is not spoken by turn {}", number, limit),
+      }
+      return Ok(());
+    }
+    // `day15 stats TURN [NUMBERS|FILE]` describes the game up to TURN.
+    Some("stats") => {
+      let turn = turn_arg(1)?;
+      let mut game = MemoryGame::new(&parse_starting_numbers(&read_input(args.get(2))?)?);
+      game.play_until(turn)?;
+      print_stats(&game);
+      return Ok(());
+    }
+    // `day15 resume CHECKPOINT TURN [NUMBERS|FILE]` plays up to TURN in a
+    // game that can be interrupted and resumed.
+    Some("resume") => {
+      let path = args.get(1).ok_or(anyhow!("Expected a checkpoint file"))?;
+      let turn = turn_arg(2)?;
+      let nums = parse_starting_numbers(&read_input(args.get(3))?)?;
+      print_stats(&run_with_checkpoints(path, &nums, turn)?);
+      return Ok(());
+    }
+    _ => (),
+  }
+
+  // `day15 [NUMBERS|FILE] [TURN]` plays from other starting numbers, and
+  // prints the number spoken on TURN instead of the two parts.
+  let nums = parse_starting_numbers(&read_input(args.first())?)?;
+  if let Some(turn) = args.get(1) {
+    let turn = turn.parse::<u64>()?;
+    let spoken = MemoryGame::nth_spoken(&nums, turn).ok_or(anyhow!("No turn {}", turn))?;
+    println!("Turn {} {}", turn, spoken);
+    return Ok(());
+  }
+
+  run(1, &nums, 2020)?;
+  run(2, &nums, 30000000)?;
   Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_example() -> anyhow::Result<()> {
+    let nums = parse_starting_numbers("0,3,6\n")?;
+    let first_ten = MemoryGame::new(&nums).take(10).collect::<Vec<_>>();
+    assert_eq!(first_ten, vec![0, 3, 6, 0, 3, 3, 1, 0, 4, 0]);
+    assert_eq!(MemoryGame::nth_spoken(&nums, 2020), Some(436));
+    assert_eq!(MemoryGame::nth_spoken(&[1, 3, 2], 2020), Some(1));
+    assert_eq!(MemoryGame::nth_spoken(&[3, 1, 2], 2020), Some(1836));
+    assert_eq!(MemoryGame::nth_spoken(&nums, 30000000), Some(175594));
+    assert_eq!(MemoryGame::nth_spoken(&nums, 0), None);
+    Ok(())
+  }
+
+  #[test]
+  fn test_starting_numbers() -> anyhow::Result<()> {
+    // Starting numbers may repeat, or be too large for the flat table.
+    let game = MemoryGame::new(&[5, 5, u64::MAX, 5]).take(7).collect::<Vec<_>>();
+    assert_eq!(game, vec![5, 5, u64::MAX, 5, 2, 0, 0]);
+    let game = MemoryGame::new(&[FLAT_LIMIT, 1, FLAT_LIMIT]).take(5).collect::<Vec<_>>();
+    assert_eq!(game, vec![FLAT_LIMIT, 1, FLAT_LIMIT, 2, 0]);
+
+    assert_eq!(parse_starting_numbers(" 1, 2 ,3\n")?, vec![1, 2, 3]);
+    assert!(parse_starting_numbers("1,x").is_err());
+    assert!(parse_starting_numbers("").is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_analysis() -> anyhow::Result<()> {
+    // 0, 3, 6, 0, 3, 3, 1, 0, 4, 0
+    let nums = [0, 3, 6];
+    assert_eq!(MemoryGame::first_spoken(&nums, 4, 10), Some(9));
+    assert_eq!(MemoryGame::first_spoken(&nums, 3, 10), Some(2));
+    assert_eq!(MemoryGame::first_spoken(&nums, 5, 10), None);
+    // The table grows with the numbers spoken, not with the limit.
+    assert_eq!(MemoryGame::first_spoken(&nums, 4, u64::MAX), Some(9));
+
+    let mut game = MemoryGame::new(&nums);
+    assert_eq!(game.play_until(10)?, 0);
+    assert_eq!(game.distinct, 5);
+    assert_eq!(
+      game.largest_gap,
+      Some(Gap {
+        number: 0,
+        length: 4,
+        turn: 8
+      })
+    );
+    assert_eq!(game.play_until(10)?, 0);
+    assert!(game.play_until(9).is_err());
+    let err = game.play_until(0).unwrap_err().to_string();
+    assert!(err.contains("start at 1"), "{}", err);
+    Ok(())
+  }
+
+  #[test]
+  fn test_flat_limit() -> anyhow::Result<()> {
+    // With a small limit, the larger numbers go in the hash map until the
+    // game is played far enough to raise it.
+    let mut game = MemoryGame::new(&[0, 3, 6]);
+    game.flat_limit = 4;
+    assert_eq!(game.play_until(9)?, 4);
+    assert_eq!(game.flat_limit, 9);
+    assert!(game.last_seen_large.is_empty());
+    let mut game = MemoryGame::new(&[0, 3, 6]);
+    game.flat_limit = 4;
+    assert_eq!(game.by_ref().take(9).last(), Some(4));
+    let mut large = game.last_seen_large.keys().copied().collect::<Vec<_>>();
+    large.sort_unstable();
+    assert_eq!(large, vec![4, 6]);
+    assert_eq!(game.play_until(2020)?, 436);
+    assert!(game.last_seen_large.is_empty());
+    assert!(game.last_seen.len() <= 2020);
+    Ok(())
+  }
+
+  #[test]
+  fn test_large_turns() -> anyhow::Result<()> {
+    // Turns too large for the table go in the hash map, as if the game had
+    // been played that far.
+    let mut game = MemoryGame::new(&[]);
+    let turn = TURN_IN_MAP as u64 - 1;
+    assert_eq!(game.remember(5, turn), 0);
+    assert_eq!(game.last_seen[5], turn as u32);
+    assert_eq!(game.remember(5, turn + 1), turn);
+    assert_eq!(game.last_seen[5], TURN_IN_MAP);
+    assert_eq!(game.remember(5, turn + 10), turn + 1);
+    assert_eq!(game.last_seen_large[&5], turn + 10);
+    assert_eq!(game.remember(6, turn + 11), 0);
+    assert_eq!(game.last_seen_large[&6], turn + 11);
+
+    // Raising the limit moves small turns into the table, and leaves large
+    // ones in the map.
+    let mut game = MemoryGame::new(&[]);
+    game.flat_limit = 4;
+    game.remember(4, 7);
+    game.remember(5, turn + 1);
+    game.raise_flat_limit(8);
+    assert_eq!(&game.last_seen[4..], &[7, TURN_IN_MAP]);
+    assert_eq!(game.last_seen_large.keys().collect::<Vec<_>>(), vec![&5]);
+    assert_eq!(game.remember(5, turn + 2), turn + 1);
+    assert_eq!(game.remember(4, turn + 3), 7);
+    Ok(())
+  }
+
+  #[test]
+  fn test_checkpoint() -> anyhow::Result<()> {
+    let path = std::env::temp_dir().join(format!("day15-test-{}.ckpt", std::process::id()));
+    let path = path.to_str().unwrap();
+    let nums = [0, 3, 6, FLAT_LIMIT];
+
+    let mut game = MemoryGame::new(&nums);
+    game.play_until(1000)?;
+    game.save(path)?;
+    let mut resumed = MemoryGame::load(path)?;
+    std::fs::remove_file(path)?;
+    assert_eq!(resumed.turn, 1000);
+    assert_eq!(resumed.last_spoken, game.last_spoken);
+    assert_eq!(resumed.distinct, game.distinct);
+    assert_eq!(resumed.largest_gap, game.largest_gap);
+    assert_eq!(resumed.play_until(5000)?, game.play_until(5000)?);
+    assert_eq!(resumed.distinct, game.distinct);
+
+    // A game that hasn't started yet.
+    MemoryGame::new(&nums).save(path)?;
+    let resumed = MemoryGame::load(path)?;
+    std::fs::remove_file(path)?;
+    assert_eq!(resumed.last_spoken, None);
+    assert_eq!(resumed.take(4).collect::<Vec<_>>(), nums.to_vec());
+
+    std::fs::write(path, "0,3,6")?;
+    assert!(MemoryGame::load(path).is_err());
+    std::fs::remove_file(path)?;
+    Ok(())
+  }
+}
@@ -1,36 +1,89 @@
-//#[macro_use]
+#[macro_use]
 extern crate anyhow;
 //extern crate regex;
 //use regex::Regex;
 extern crate bit_set;
 use bit_set::BitSet;
 
-const USE_TEST_INPUT: bool = false;
-const TEST_INPUT: &str = r"class: 0-1 or 4-19
-row: 0-5 or 8-19
-seat: 0-13 or 16-19
-
-your ticket:
-11,12,13
-
-nearby tickets:
-3,9,18
-15,1,5
-5,14,9";
-
 fn read_input() -> anyhow::Result<String> {
-  if USE_TEST_INPUT {
-    Ok(TEST_INPUT.to_owned())
-  } else {
-    Ok(std::fs::read_to_string("day16/input.txt")?)
+  Ok(std::fs::read_to_string("day16/input.txt")?)
+}
+
+// Where and why the notes could not be read. Lines are numbered from 1.
+#[derive(Debug, PartialEq)]
+enum ParseError {
+  // A field rule without a name, or without ranges after its colon.
+  BadField {
+    line: usize,
+    text: String,
+  },
+  // A range which isn't `LOW-HIGH`, with LOW no more than HIGH.
+  BadRange {
+    line: usize,
+    range: String,
+  },
+  BadNumber {
+    line: usize,
+    text: String,
+  },
+  // A line out of place, such as a second "your ticket".
+  UnexpectedLine {
+    line: usize,
+    text: String,
+  },
+  MissingSection(&'static str),
+  // A ticket without a value for each field.
+  TicketLength {
+    line: usize,
+    expected: usize,
+    found: usize,
+  },
+}
+impl std::fmt::Display for ParseError {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match self {
+      ParseError::BadField { line, text } => {
+        write!(
+          f,
+          "line {}: expected `NAME: RANGE or RANGE`, got `{}`",
+          line, text
+        )
+      }
+      ParseError::BadRange { line, range } => {
+        write!(
+          f,
+          "line {}: expected a range `LOW-HIGH`, got `{}`",
+          line, range
+        )
+      }
+      ParseError::BadNumber { line, text } => {
+        write!(f, "line {}: expected a number, got `{}`", line, text)
+      }
+      ParseError::UnexpectedLine { line, text } => {
+        write!(f, "line {}: unexpected `{}`", line, text)
+      }
+      ParseError::MissingSection(section) => write!(f, "missing the `{}` section", section),
+      ParseError::TicketLength {
+        line,
+        expected,
+        found,
+      } => write!(
+        f,
+        "line {}: ticket has {} values, expected one for each of {} fields",
+        line, found, expected
+      ),
+    }
   }
 }
+impl std::error::Error for ParseError {}
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 struct FieldRange {
   low: u64,
   high: u64,
 }
 
+#[derive(Debug, PartialEq)]
 struct FieldDefn {
   name: String,
   ranges: Vec<FieldRange>,
@@ -49,236 +102,832 @@ impl std::fmt::Display for FieldDefn {
   }
 }
 impl FieldDefn {
-  fn from_str(s: &str) -> FieldDefn {
-    let mut split = s.split_terminator(": ");
-    let name = split.next().unwrap();
-    let ranges_all = split.next().unwrap();
-    assert_eq!(split.next(), None);
-    let ranges = {
-      let mut vec = Vec::<FieldRange>::new();
-      let ranges_strs = ranges_all.split_terminator(" or ");
-      for range_str in ranges_strs {
-        let mut low_high = range_str.split_terminator("-");
-        let low = low_high
-          .next()
-          .expect("bad low string")
-          .parse()
-          .expect("parse low string");
-        let high = low_high
-          .next()
-          .expect("bad high string")
-          .parse()
-          .expect("parse high string");
-        assert_eq!(low_high.next(), None);
-        vec.push(FieldRange {
-          low: low,
-          high: high,
-        });
-      }
-      vec
+  // Parses `NAME: LOW-HIGH or LOW-HIGH ...`. Ranges never hold a colon, so
+  // the name is everything before the last one, and may hold colons itself.
+  fn parse(s: &str, line: usize) -> Result<FieldDefn, ParseError> {
+    let bad_field = || ParseError::BadField {
+      line,
+      text: s.to_owned(),
     };
-    FieldDefn {
-      name: name.to_owned(),
-      ranges: ranges,
+    let colon = s.rfind(':').ok_or_else(bad_field)?;
+    let name = s[..colon].trim();
+    let ranges_all = s[colon + 1..].trim();
+    if name.is_empty() || ranges_all.is_empty() {
+      return Err(bad_field());
     }
+    let ranges = ranges_all
+      .split(" or ")
+      .map(|range_str| {
+        let range_str = range_str.trim();
+        let bad_range = || ParseError::BadRange {
+          line,
+          range: range_str.to_owned(),
+        };
+        let mut low_high = range_str.splitn(2, '-');
+        let mut bound = || -> Result<u64, ParseError> {
+          let n = low_high.next().ok_or_else(bad_range)?.trim();
+          n.parse().map_err(|_| bad_range())
+        };
+        let (low, high) = (bound()?, bound()?);
+        if low > high {
+          return Err(bad_range());
+        }
+        Ok(FieldRange { low, high })
+      })
+      .collect::<Result<Vec<_>, _>>()?;
+    Ok(FieldDefn {
+      name: name.to_owned(),
+      ranges,
+    })
   }
 }
 
-struct Inputs<'a> {
-  fields: Vec<&'a str>,
-  your_ticket: &'a str,
-  nearby_tickets: Vec<&'a str>,
+fn parse_ticket(s: &str, line: usize) -> Result<Vec<u64>, ParseError> {
+  s.split(',')
+    .map(|n| {
+      let n = n.trim();
+      n.parse().map_err(|_| ParseError::BadNumber {
+        line,
+        text: n.to_owned(),
+      })
+    })
+    .collect()
 }
-impl<'a> Inputs<'_> {
-  fn from_str(input_all: &'a str) -> Inputs<'a> {
-    let lines = input_all.split_terminator("\n").collect::<Vec<_>>();
-    let mut it = lines.iter();
-    let field_lines = {
-      let mut v = Vec::<&str>::new();
-      while let Some(s) = it.next() {
-        if *s == "" {
-          break;
+
+// The notes about tickets: the rules for each field, your ticket, and the
+// nearby tickets.
+struct Notes {
+  fields: Vec<FieldDefn>,
+  your_ticket: Vec<u64>,
+  nearby_tickets: Vec<Vec<u64>>,
+}
+impl Notes {
+  // Reads the notes section by section. Whitespace around lines and values,
+  // and blank lines anywhere, are ignored.
+  fn parse(input_all: &str) -> Result<Notes, ParseError> {
+    #[derive(PartialEq)]
+    enum Section {
+      Fields,
+      YourTicket,
+      NearbyTickets,
+    }
+    let mut section = Section::Fields;
+    let mut fields = Vec::new();
+    let mut your_ticket = None;
+    // Each nearby ticket, with its line.
+    let mut nearby_tickets = Vec::new();
+
+    for (i, text) in input_all.lines().enumerate() {
+      let line = i + 1;
+      let text = text.trim();
+      if text.is_empty() {
+        continue;
+      }
+      let unexpected = || ParseError::UnexpectedLine {
+        line,
+        text: text.to_owned(),
+      };
+      // Headers may have any spacing between their words.
+      let header = text.split_whitespace().collect::<Vec<_>>().join(" ");
+      match header.as_str() {
+        "your ticket:" | "your ticket :" => {
+          if section != Section::Fields {
+            return Err(unexpected());
+          }
+          section = Section::YourTicket;
+        }
+        "nearby tickets:" | "nearby tickets :" => {
+          if section != Section::YourTicket {
+            return Err(unexpected());
+          }
+          if your_ticket.is_none() {
+            return Err(ParseError::MissingSection("your ticket"));
+          }
+          section = Section::NearbyTickets;
         }
-        v.push(s);
+        _ => match section {
+          Section::Fields => fields.push(FieldDefn::parse(text, line)?),
+          Section::YourTicket if your_ticket.is_none() => {
+            your_ticket = Some((line, parse_ticket(text, line)?))
+          }
+          Section::YourTicket => return Err(unexpected()),
+          Section::NearbyTickets => nearby_tickets.push((line, parse_ticket(text, line)?)),
+        },
       }
-      v
-    };
-    assert_eq!(it.next(), Some(&"your ticket:"));
-    let your_ticket_line = it.next().unwrap();
-    assert_eq!(it.next(), Some(&""));
-    assert_eq!(it.next(), Some(&"nearby tickets:"));
-    let nearby_ticket_lines = {
-      let mut v = Vec::<&str>::new();
-      while let Some(s) = it.next() {
-        v.push(s);
+    }
+
+    if fields.is_empty() {
+      return Err(ParseError::MissingSection("fields"));
+    }
+    let your_ticket = your_ticket.ok_or(ParseError::MissingSection("your ticket"))?;
+    if section != Section::NearbyTickets {
+      return Err(ParseError::MissingSection("nearby tickets"));
+    }
+    for (line, ticket) in std::iter::once(&your_ticket).chain(&nearby_tickets) {
+      if ticket.len() != fields.len() {
+        return Err(ParseError::TicketLength {
+          line: *line,
+          expected: fields.len(),
+          found: ticket.len(),
+        });
       }
-      v
-    };
-    Inputs {
-      fields: field_lines,
-      your_ticket: your_ticket_line,
-      nearby_tickets: nearby_ticket_lines,
     }
+    Ok(Notes {
+      fields,
+      your_ticket: your_ticket.1,
+      nearby_tickets: nearby_tickets.into_iter().map(|(_, t)| t).collect(),
+    })
   }
+}
 
-  fn get_field_defns(&self) -> Vec<FieldDefn> {
-    self.fields.iter().map(|s| FieldDefn::from_str(s)).collect()
+// Sorted ranges, with overlapping and touching ranges merged, so finding
+// whether a value is in any of them is a binary search.
+struct IntervalSet {
+  ranges: Vec<FieldRange>,
+}
+impl IntervalSet {
+  fn new<'a, I: IntoIterator<Item = &'a FieldRange>>(ranges: I) -> IntervalSet {
+    let mut sorted = ranges
+      .into_iter()
+      .filter(|r| r.low <= r.high)
+      .copied()
+      .collect::<Vec<_>>();
+    sorted.sort_by_key(|r| r.low);
+    let mut merged = Vec::<FieldRange>::new();
+    for r in sorted {
+      match merged.last_mut() {
+        Some(last) if r.low <= last.high.saturating_add(1) => last.high = last.high.max(r.high),
+        _ => merged.push(r),
+      }
+    }
+    IntervalSet { ranges: merged }
   }
 
-  fn get_nearby_tickets(&self) -> Vec<Vec<u64>> {
-    let mut nearbys = Vec::new();
-    for nearby in &self.nearby_tickets {
-      let split = nearby.split_terminator(",");
-      nearbys.push(split.map(|s| s.parse().unwrap()).collect());
+  fn contains(&self, n: u64) -> bool {
+    let i = self.ranges.partition_point(|r| r.high < n);
+    i < self.ranges.len() && self.ranges[i].low <= n
+  }
+}
+
+// For any value, a bitmask of the field definitions it fits, by index. The
+// field ranges split the numbers into segments where the same fields fit,
+// so this keeps a mask for each segment and binary searches for the
+// segment of a value.
+struct FieldMasks {
+  // Where each segment starts, beginning at 0.
+  starts: Vec<u64>,
+  masks: Vec<u64>,
+}
+impl FieldMasks {
+  const MAX_FIELDS: usize = 64;
+
+  fn new(field_defns: &[FieldDefn]) -> anyhow::Result<FieldMasks> {
+    if field_defns.len() > Self::MAX_FIELDS {
+      return Err(anyhow!(
+        "{} fields is more than the {} supported",
+        field_defns.len(),
+        Self::MAX_FIELDS
+      ));
+    }
+    let mut starts = vec![0];
+    for r in field_defns.iter().flat_map(|f| &f.ranges) {
+      starts.push(r.low);
+      if let Some(end) = r.high.checked_add(1) {
+        starts.push(end);
+      }
     }
-    nearbys
+    starts.sort_unstable();
+    starts.dedup();
+    let masks = starts
+      .iter()
+      .map(|&n| {
+        let mut mask = 0;
+        for (d, f) in field_defns.iter().enumerate() {
+          if f.ranges.iter().any(|r| n >= r.low && n <= r.high) {
+            mask |= 1 << d;
+          }
+        }
+        mask
+      })
+      .collect();
+    Ok(FieldMasks { starts, masks })
   }
 
-  fn get_your_ticket(&self) -> Vec<u64> {
-    self
-      .your_ticket
-      .split_terminator(",")
-      .map(|s| s.parse().unwrap())
-      .collect()
+  fn fields_matching(&self, n: u64) -> u64 {
+    let i = self.starts.partition_point(|&s| s <= n);
+    self.masks[i - 1]
   }
 }
 
-fn p1(input_all: &str) -> anyhow::Result<()> {
-  let inputs = Inputs::from_str(input_all);
+// The values on a nearby ticket which don't fit any field.
+#[derive(Debug, PartialEq)]
+struct InvalidTicket {
+  // Which nearby ticket it is, from 0.
+  index: usize,
+  // The position and value of each value which doesn't fit.
+  values: Vec<(usize, u64)>,
+}
+impl std::fmt::Display for InvalidTicket {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    let values = self
+      .values
+      .iter()
+      .map(|(pos, val)| format!("{} at position {}", val, pos))
+      .collect::<Vec<_>>();
+    write!(f, "Ticket {}: {}", self.index, values.join(", "))
+  }
+}
 
-  let mut bad_sum = 0;
+// Validates tickets against the field definitions.
+struct Rules {
+  // Values which fit any field.
+  any_field: IntervalSet,
+  fields: FieldMasks,
+}
+impl Rules {
+  fn new(field_defns: &[FieldDefn]) -> anyhow::Result<Rules> {
+    Ok(Rules {
+      any_field: IntervalSet::new(field_defns.iter().flat_map(|f| &f.ranges)),
+      fields: FieldMasks::new(field_defns)?,
+    })
+  }
 
-  let field_defns: Vec<FieldDefn> = inputs.get_field_defns();
-  for nearby_str in inputs.nearby_tickets {
-    let nearby_field_nums: Vec<u64> = nearby_str
-      .split_terminator(",")
-      .map(|s| s.parse().unwrap())
-      .collect();
-    for n in nearby_field_nums {
-      let mut found = false;
-      'search: for field_defn in &field_defns {
-        for range in &field_defn.ranges {
-          if n >= range.low && n <= range.high {
-            found = true;
-            break 'search;
-          }
+  fn is_valid_ticket(&self, ticket: &[u64]) -> bool {
+    ticket.iter().all(|&n| self.any_field.contains(n))
+  }
+
+  // Lists each ticket with values which don't fit any field.
+  fn scan(&self, tickets: &[Vec<u64>]) -> Vec<InvalidTicket> {
+    tickets
+      .iter()
+      .enumerate()
+      .filter_map(|(index, ticket)| {
+        let values = ticket
+          .iter()
+          .copied()
+          .enumerate()
+          .filter(|&(_, n)| !self.any_field.contains(n))
+          .collect::<Vec<_>>();
+        if values.is_empty() {
+          None
+        } else {
+          Some(InvalidTicket { index, values })
         }
-      }
-      if !found {
-        bad_sum += n;
-      }
-    }
+      })
+      .collect()
   }
-  println!("Part 1 {}", bad_sum);
-  Ok(())
 }
 
-fn p2(input_all: &str) -> anyhow::Result<()> {
-  let inputs = Inputs::from_str(input_all);
-  let field_defns = inputs.get_field_defns();
-  let num_fields = field_defns.len();
+// The sum of every value on nearby tickets that doesn't fit any field.
+fn error_rate(report: &[InvalidTicket]) -> u64 {
+  report
+    .iter()
+    .flat_map(|t| &t.values)
+    .map(|(_, val)| val)
+    .sum()
+}
+
+fn p1(notes: &Notes) -> anyhow::Result<u64> {
+  let rules = Rules::new(&notes.fields)?;
+  Ok(error_rate(&rules.scan(&notes.nearby_tickets)))
+}
+
+// How many solutions to look for before giving up on listing them all.
+const SOLUTION_LIMIT: usize = 1000;
+
+// Assigns each field definition to a different ticket position, given the
+// positions each one is possible in.
+struct FieldMatcher {
+  // The possible positions of each field definition, by index.
+  possible: Vec<BitSet>,
+  num_positions: usize,
+}
+
+#[derive(Debug, PartialEq)]
+enum Assignment {
+  // The position of each field definition, by index.
+  Unique(Vec<usize>),
+  Ambiguous {
+    // Every solution, up to SOLUTION_LIMIT of them.
+    solutions: Vec<Vec<usize>>,
+    // The field definitions whose position differs between solutions.
+    undetermined: Vec<usize>,
+  },
+  Unsatisfiable {
+    // Field definitions which have fewer possible positions among them than
+    // there are definitions.
+    conflicting: Vec<usize>,
+    // The positions they could go in.
+    positions: Vec<usize>,
+  },
+}
 
-  let mut possible_fields = std::collections::HashMap::</*FieldDefn.name=*/ String, BitSet>::new();
-  // Mark every field as possible for each FieldDefn.
-  for field in &field_defns {
-    let mut bitset = BitSet::with_capacity(num_fields);
-    for i in 0..num_fields {
-      bitset.insert(i);
+impl FieldMatcher {
+  fn new(possible: Vec<BitSet>, num_positions: usize) -> FieldMatcher {
+    FieldMatcher {
+      possible,
+      num_positions,
     }
-    possible_fields.insert(field.name.clone(), bitset);
   }
 
-  let nearbys: Vec<Vec<u64>> = {
-    let all_nearbys = inputs.get_nearby_tickets();
-    // Drop invalid tickets from the `inputs`.
-    let is_valid_ticket = |ticket: &Vec<u64>| {
-      'search: for &n in ticket.iter() {
-        for field_defn in &field_defns {
-          for range in &field_defn.ranges {
-            if n >= range.low && n <= range.high {
-              continue 'search;
-            }
-          }
-        }
-        return false;
-      }
-      true
-    };
-    all_nearbys.into_iter().filter(is_valid_ticket).collect()
-  };
-  // Walk through the nearby tickets eliminating possible field indices from FieldDefns.
-  for n in nearbys {
-    for (i, val) in n.into_iter().enumerate() {
-      // For the `i`th field on a ticket, see if this value excludes any FieldDefn.
-      for fdefn in &field_defns {
-        let mut fits_field_defn = false;
-        for frange in &fdefn.ranges {
-          if val >= frange.low && val <= frange.high {
-            fits_field_defn = true;
-            break;
-          }
+  // Finds the largest matching of field definitions to positions, by looking
+  // for an augmenting path from each definition in turn. Returns the position
+  // of each definition, where it has one.
+  fn max_matching(&self) -> Vec<Option<usize>> {
+    // Tries to give `defn` a position, moving other definitions to other
+    // positions as needed.
+    fn augment(
+      possible: &[BitSet],
+      defn: usize,
+      owner: &mut Vec<Option<usize>>,
+      visited: &mut BitSet,
+    ) -> bool {
+      for pos in possible[defn].iter() {
+        if !visited.insert(pos) {
+          continue;
         }
-        if !fits_field_defn {
-          // Remove `i` from the possible field indices for FieldDefn.
-          possible_fields.get_mut(&fdefn.name).unwrap().remove(i);
+        let free = match owner[pos] {
+          None => true,
+          Some(other) => augment(possible, other, owner, visited),
+        };
+        if free {
+          owner[pos] = Some(defn);
+          return true;
         }
       }
+      false
     }
+
+    let mut owner = vec![None; self.num_positions];
+    for defn in 0..self.possible.len() {
+      augment(
+        &self.possible,
+        defn,
+        &mut owner,
+        &mut BitSet::with_capacity(self.num_positions),
+      );
+    }
+    let mut matching = vec![None; self.possible.len()];
+    for (pos, defn) in owner.into_iter().enumerate() {
+      if let Some(defn) = defn {
+        matching[defn] = Some(pos);
+      }
+    }
+    matching
   }
 
-  // For each FieldDefn that has only a single possible field index, remove that
-  // field index from the other FieldDefns, and repeat this recursively. We should
-  // have a single field index per FieldDefn at the end in order to solve the
-  // problem!
-  fn reduce(
-    possible_fields: &mut std::collections::HashMap<String, BitSet>,
-    field_defns: &Vec<FieldDefn>,
-    fdefn: &FieldDefn,
-  ) {
-    assert_eq!(possible_fields[&fdefn.name].len(), 1);
-    for other_fdefn in field_defns {
-      assert!(possible_fields[&other_fdefn.name].len() >= 1);
-      if fdefn.name == other_fdefn.name {
-        continue;
+  // When a maximum matching leaves a definition without a position, the
+  // definitions reachable from it by alternating paths have too few possible
+  // positions between them (Hall's theorem). Returns those definitions and
+  // their positions.
+  fn conflict(&self, matching: &[Option<usize>]) -> Option<(Vec<usize>, Vec<usize>)> {
+    let unmatched = matching.iter().position(|m| m.is_none())?;
+    let mut owner = vec![None; self.num_positions];
+    for (defn, pos) in matching.iter().enumerate() {
+      if let Some(pos) = pos {
+        owner[*pos] = Some(defn);
       }
-      if possible_fields[&other_fdefn.name].len() == 1 {
-        continue;
+    }
+    let mut defns = BitSet::new();
+    let mut positions = BitSet::new();
+    let mut queue = vec![unmatched];
+    defns.insert(unmatched);
+    while let Some(defn) = queue.pop() {
+      for pos in self.possible[defn].iter() {
+        if positions.insert(pos) {
+          // Every position reached is matched, or the matching would not be
+          // maximum.
+          let next = owner[pos].unwrap();
+          if defns.insert(next) {
+            queue.push(next);
+          }
+        }
       }
+    }
+    Some((defns.iter().collect(), positions.iter().collect()))
+  }
 
-      let remove_bit = possible_fields[&fdefn.name].iter().next().unwrap();
-      possible_fields
-        .get_mut(&other_fdefn.name)
-        .unwrap()
-        .remove(remove_bit);
-      if possible_fields[&other_fdefn.name].len() == 1 {
-        // Recurse if this fdefn now has a unique field index, to remove
-        // that field index from the rest.
-        reduce(possible_fields, field_defns, other_fdefn);
+  // Finds up to `limit` ways to assign every definition a different
+  // position, by backtracking on the definition with the fewest positions
+  // left.
+  fn solutions(&self, limit: usize) -> Vec<Vec<usize>> {
+    fn search(
+      possible: &[BitSet],
+      assigned: &mut Vec<Option<usize>>,
+      used: &mut BitSet,
+      limit: usize,
+      solutions: &mut Vec<Vec<usize>>,
+    ) {
+      let next = (0..possible.len())
+        .filter(|&d| assigned[d].is_none())
+        .min_by_key(|&d| possible[d].difference(used).count());
+      let defn = match next {
+        Some(defn) => defn,
+        None => {
+          solutions.push(assigned.iter().map(|p| p.unwrap()).collect());
+          return;
+        }
+      };
+      let choices = possible[defn].difference(used).collect::<Vec<_>>();
+      for pos in choices {
+        if solutions.len() >= limit {
+          return;
+        }
+        assigned[defn] = Some(pos);
+        used.insert(pos);
+        search(possible, assigned, used, limit, solutions);
+        used.remove(pos);
+        assigned[defn] = None;
       }
     }
+
+    let mut solutions = Vec::new();
+    search(
+      &self.possible,
+      &mut vec![None; self.possible.len()],
+      &mut BitSet::with_capacity(self.num_positions),
+      limit,
+      &mut solutions,
+    );
+    solutions
+  }
+
+  fn solve(&self) -> Assignment {
+    let matching = self.max_matching();
+    if let Some((conflicting, positions)) = self.conflict(&matching) {
+      return Assignment::Unsatisfiable {
+        conflicting,
+        positions,
+      };
+    }
+    let mut solutions = self.solutions(SOLUTION_LIMIT);
+    if solutions.len() == 1 {
+      return Assignment::Unique(solutions.pop().unwrap());
+    }
+    let undetermined = (0..self.possible.len())
+      .filter(|&d| solutions.iter().any(|s| s[d] != solutions[0][d]))
+      .collect();
+    Assignment::Ambiguous {
+      solutions,
+      undetermined,
+    }
   }
-  for fdefn in &field_defns {
-    if possible_fields[&fdefn.name].len() == 1 {
-      reduce(&mut possible_fields, &field_defns, fdefn);
+}
+
+// The ticket positions that each field definition could be in, by index,
+// given the valid nearby tickets.
+fn possible_fields(rules: &Rules, num_fields: usize, nearbys: &[Vec<u64>]) -> Vec<BitSet> {
+  // The field definitions which fit every value seen at each position.
+  let mut fits = vec![!0_u64; num_fields];
+  for ticket in nearbys {
+    for (i, &val) in ticket.iter().enumerate() {
+      fits[i] &= rules.fields.fields_matching(val);
     }
   }
+  (0..num_fields)
+    .map(|d| (0..num_fields).filter(|&i| fits[i] >> d & 1 == 1).collect())
+    .collect()
+}
+
+// The position on the tickets of each field, by index.
+fn field_positions(notes: &Notes) -> anyhow::Result<Vec<usize>> {
+  let field_defns = &notes.fields;
+  let num_fields = field_defns.len();
 
-  let your_ticket: Vec<u64> = inputs.get_your_ticket();
+  let rules = Rules::new(field_defns)?;
+  // Drop invalid tickets from the notes.
+  let nearbys: Vec<Vec<u64>> = notes
+    .nearby_tickets
+    .iter()
+    .filter(|t| rules.is_valid_ticket(t))
+    .cloned()
+    .collect();
 
+  let matcher = FieldMatcher::new(possible_fields(&rules, num_fields, &nearbys), num_fields);
+  let names = |defns: &[usize]| {
+    defns
+      .iter()
+      .map(|&d| field_defns[d].name.as_str())
+      .collect::<Vec<_>>()
+      .join(", ")
+  };
+  let positions = match matcher.solve() {
+    Assignment::Unique(positions) => positions,
+    Assignment::Ambiguous {
+      solutions,
+      undetermined,
+    } => {
+      // The error lists each solution on its own line.
+      let listed = solutions
+        .iter()
+        .map(|solution| {
+          let fields = undetermined
+            .iter()
+            .map(|&d| format!("{}={}", field_defns[d].name, solution[d]))
+            .collect::<Vec<_>>();
+          format!("\n  {}", fields.join(" "))
+        })
+        .collect::<String>();
+      return Err(anyhow!(
+        "Ambiguous: {} solutions for the positions of {}:{}",
+        solutions.len(),
+        names(&undetermined),
+        listed
+      ));
+    }
+    Assignment::Unsatisfiable {
+      conflicting,
+      positions,
+    } => {
+      return Err(anyhow!(
+        "Unsatisfiable: {} fit only in positions {:?}",
+        names(&conflicting),
+        positions
+      ));
+    }
+  };
+
+  Ok(positions)
+}
+
+fn p2(notes: &Notes) -> anyhow::Result<u64> {
+  let positions = field_positions(notes)?;
   let mut depart_prod = 1;
-  for fdefn in &field_defns {
-    assert_eq!(possible_fields[&fdefn.name].len(), 1);
+  for (fdefn, &pos) in notes.fields.iter().zip(&positions) {
     if fdefn.name.starts_with("departure") {
-      depart_prod *= your_ticket[possible_fields[&fdefn.name].iter().next().unwrap()];
+      depart_prod *= notes.your_ticket[pos];
     }
   }
-  println!("Part 2 {}", depart_prod);
+  Ok(depart_prod)
+}
+
+// Prints each nearby ticket with values that don't fit any field.
+fn print_report(notes: &Notes) -> anyhow::Result<()> {
+  let rules = Rules::new(&notes.fields)?;
+  let report = rules.scan(&notes.nearby_tickets);
+  for invalid in &report {
+    println!("{}", invalid);
+  }
+  println!(
+    "{} invalid tickets, error rate {}",
+    report.len(),
+    error_rate(&report)
+  );
   Ok(())
 }
 
 fn main() -> anyhow::Result<()> {
-  let input_all = read_input()?;
-  p1(&input_all)?;
-  p2(&input_all)?;
+  let notes = Notes::parse(&read_input()?)?;
+  // `day16 report` lists the invalid nearby tickets.
+  if std::env::args().nth(1).as_deref() == Some("report") {
+    return print_report(&notes);
+  }
+  println!("Part 1 {}", p1(&notes)?);
+  println!("Part 2 {}", p2(&notes)?);
   Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const TEST_INPUT_1: &str = "class: 1-3 or 5-7
+row: 6-11 or 33-44
+seat: 13-40 or 45-50
+
+your ticket:
+7,1,14
+
+nearby tickets:
+7,3,47
+40,4,50
+55,2,20
+38,6,12
+";
+  const TEST_INPUT_2: &str = "class: 0-1 or 4-19
+row: 0-5 or 8-19
+seat: 0-13 or 16-19
+
+your ticket:
+11,12,13
+
+nearby tickets:
+3,9,18
+15,1,5
+5,14,9
+";
+
+  #[test]
+  fn test_example() -> anyhow::Result<()> {
+    assert_eq!(p1(&Notes::parse(TEST_INPUT_1)?)?, 71);
+    let notes = Notes::parse(TEST_INPUT_2)?;
+    assert_eq!(field_positions(&notes)?, vec![1, 0, 2]);
+    // There are no departure fields.
+    assert_eq!(p2(&notes)?, 1);
+
+    let notes = Notes::parse(
+      "a: 1-5 or 6-10\nb: 1-5 or 6-10\n\nyour ticket:\n1,2\n\nnearby tickets:\n3,4\n",
+    )?;
+    assert_eq!(
+      field_positions(&notes).unwrap_err().to_string(),
+      "Ambiguous: 2 solutions for the positions of a, b:\n  a=0 b=1\n  a=1 b=0"
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn test_parse() -> anyhow::Result<()> {
+    // Extra whitespace and blank lines, CRLF line endings, and names with
+    // colons or outside ASCII.
+    let input = "\n  class: 1-3 or 5-7  \r\n\nrow:col: 6 - 11 or 33-44\r\nsiège: 13-40 or 45-50\n\n\n your   ticket:\n 7, 1 ,14 \n\nnearby tickets :\n\n7,3,47\n\n40,4,50\n\n";
+    let notes = Notes::parse(input)?;
+    let names = notes
+      .fields
+      .iter()
+      .map(|f| f.name.as_str())
+      .collect::<Vec<_>>();
+    assert_eq!(names, vec!["class", "row:col", "siège"]);
+    assert_eq!(notes.fields[1].to_string(), "row:col: 6-11 or 33-44");
+    assert_eq!(notes.your_ticket, vec![7, 1, 14]);
+    assert_eq!(notes.nearby_tickets, vec![vec![7, 3, 47], vec![40, 4, 50]]);
+
+    let error = |input: &str| Notes::parse(input).err().unwrap();
+    assert_eq!(
+      error("class 1-3\nyour ticket:\n1\nnearby tickets:\n"),
+      ParseError::BadField {
+        line: 1,
+        text: "class 1-3".to_owned()
+      }
+    );
+    assert_eq!(
+      error("class: 1-3 or 7\nyour ticket:\n1\nnearby tickets:\n"),
+      ParseError::BadRange {
+        line: 1,
+        range: "7".to_owned()
+      }
+    );
+    assert_eq!(
+      error("class: 3-1\nyour ticket:\n1\nnearby tickets:\n"),
+      ParseError::BadRange {
+        line: 1,
+        range: "3-1".to_owned()
+      }
+    );
+    assert_eq!(
+      error("class: 1-3\nyour ticket:\n1\nnearby tickets:\n2,x\n"),
+      ParseError::BadNumber {
+        line: 5,
+        text: "x".to_owned()
+      }
+    );
+    assert_eq!(
+      error("class: 1-3\nyour ticket:\n1\n2\nnearby tickets:\n"),
+      ParseError::UnexpectedLine {
+        line: 4,
+        text: "2".to_owned()
+      }
+    );
+    assert_eq!(
+      error("class: 1-3\nyour ticket:\nnearby tickets:\n"),
+      ParseError::MissingSection("your ticket")
+    );
+    assert_eq!(
+      error("class: 1-3\nyour ticket:\n1\n"),
+      ParseError::MissingSection("nearby tickets")
+    );
+    assert_eq!(
+      error("class: 1-3\nyour ticket:\n1\nnearby tickets:\n1,2\n"),
+      ParseError::TicketLength {
+        line: 5,
+        expected: 1,
+        found: 2
+      }
+    );
+    assert_eq!(error("").to_string(), "missing the `fields` section");
+    Ok(())
+  }
+
+  fn matcher(possible: &[&[usize]]) -> FieldMatcher {
+    let possible = possible
+      .iter()
+      .map(|p| p.iter().copied().collect::<BitSet>())
+      .collect::<Vec<_>>();
+    let num_positions = possible.len();
+    FieldMatcher::new(possible, num_positions)
+  }
+
+  #[test]
+  fn test_field_matcher() {
+    // Needs more than eliminating definitions with a single position: 0 and
+    // 1 share positions 0 and 1, so 2 must take position 2.
+    let m = matcher(&[&[0, 1], &[0, 1], &[0, 1, 2]]);
+    assert_eq!(
+      m.solve(),
+      Assignment::Ambiguous {
+        solutions: vec![vec![0, 1, 2], vec![1, 0, 2]],
+        undetermined: vec![0, 1],
+      }
+    );
+    let m = matcher(&[&[0, 1], &[1], &[0, 1, 2]]);
+    assert_eq!(m.solve(), Assignment::Unique(vec![0, 1, 2]));
+    let m = matcher(&[&[0, 2], &[0, 1, 2], &[2], &[0, 1, 2, 3]]);
+    assert_eq!(m.solve(), Assignment::Unique(vec![0, 1, 2, 3]));
+
+    // Three definitions fit in only two positions.
+    let m = matcher(&[&[1, 2], &[0, 1, 2, 3], &[1, 2], &[2]]);
+    assert_eq!(
+      m.solve(),
+      Assignment::Unsatisfiable {
+        conflicting: vec![0, 2, 3],
+        positions: vec![1, 2],
+      }
+    );
+    let m = matcher(&[&[0], &[]]);
+    assert_eq!(
+      m.solve(),
+      Assignment::Unsatisfiable {
+        conflicting: vec![1],
+        positions: vec![],
+      }
+    );
+
+    // Listing solutions stops at the limit.
+    let all = vec![0, 1, 2, 3, 4];
+    let m = matcher(&[&all, &all, &all, &all, &all]);
+    assert_eq!(m.solutions(usize::MAX).len(), 120);
+    assert_eq!(m.solutions(7).len(), 7);
+  }
+
+  fn field(s: &str) -> FieldDefn {
+    FieldDefn::parse(s, 1).unwrap()
+  }
+
+  #[test]
+  fn test_rules() -> anyhow::Result<()> {
+    let set = IntervalSet::new(&[
+      FieldRange { low: 10, high: 20 },
+      FieldRange { low: 1, high: 3 },
+      FieldRange { low: 4, high: 5 },
+      FieldRange { low: 15, high: 30 },
+      FieldRange { low: 9, high: 8 },
+      FieldRange {
+        low: 40,
+        high: u64::MAX,
+      },
+    ]);
+    assert_eq!(
+      set.ranges,
+      vec![
+        FieldRange { low: 1, high: 5 },
+        FieldRange { low: 10, high: 30 },
+        FieldRange {
+          low: 40,
+          high: u64::MAX
+        }
+      ]
+    );
+    let contained = (0..=45).filter(|&n| set.contains(n)).count();
+    assert_eq!(contained, 5 + 21 + 6);
+    assert!(set.contains(u64::MAX));
+
+    let field_defns = vec![
+      field("class: 1-3 or 5-7"),
+      field("row: 6-11 or 33-44"),
+      field("seat: 13-40 or 45-50"),
+    ];
+    let rules = Rules::new(&field_defns)?;
+    assert_eq!(rules.fields.fields_matching(0), 0);
+    assert_eq!(rules.fields.fields_matching(6), 0b011);
+    assert_eq!(rules.fields.fields_matching(35), 0b110);
+    assert_eq!(rules.fields.fields_matching(51), 0);
+
+    let tickets = vec![
+      vec![7, 3, 47],
+      vec![40, 4, 50],
+      vec![55, 2, 20],
+      vec![38, 6, 12],
+    ];
+    let report = rules.scan(&tickets);
+    assert_eq!(
+      report,
+      vec![
+        InvalidTicket {
+          index: 1,
+          values: vec![(1, 4)]
+        },
+        InvalidTicket {
+          index: 2,
+          values: vec![(0, 55)]
+        },
+        InvalidTicket {
+          index: 3,
+          values: vec![(2, 12)]
+        },
+      ]
+    );
+    assert_eq!(error_rate(&report), 71);
+    assert_eq!(report[0].to_string(), "Ticket 1: 4 at position 1");
+    assert!(rules.is_valid_ticket(&tickets[0]));
+    Ok(())
+  }
+}
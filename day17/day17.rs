@@ -1,4 +1,4 @@
-// #[macro_use]
+#[macro_use]
 extern crate anyhow;
 //extern crate regex;
 //use regex::Regex;
@@ -7,6 +7,16 @@ fn read_input() -> anyhow::Result<String> {
   Ok(std::fs::read_to_string("day17/input.txt")?)
 }
 
+#[path = "../automaton/automaton.rs"]
+mod automaton;
+use automaton::{Automaton, HashMap, Lattice, Rules, Topology};
+
+// An active cube stays active with 2 or 3 active neighbours, and an inactive
+// cube becomes active with exactly 3.
+fn conway_rules() -> Rules {
+  Rules::new(3..=3, 2..=3)
+}
+
 struct DimensionRange {
   min: i64,
   max: i64,
@@ -22,234 +32,357 @@ impl DimensionRange {
   }
 }
 
-struct Dimension3 {
-  empty: bool,
-  values: std::collections::HashSet<(i64, i64, i64)>,
-  xrange: DimensionRange,
-  yrange: DimensionRange,
-  zrange: DimensionRange,
+// Conway cubes in N dimensions, which start from a 2D slice of the pocket
+// dimension where every other coordinate is 0.
+struct ConwayCubes<const N: usize> {
+  cubes: Automaton<Lattice<N>>,
 }
-impl Dimension3 {
-  fn new() -> Self {
-    Self {
-      empty: true,
-      values: std::collections::HashSet::new(),
-      xrange: DimensionRange { min: 0, max: 0 },
-      yrange: DimensionRange { min: 0, max: 0 },
-      zrange: DimensionRange { min: 0, max: 0 },
-    }
+impl<const N: usize> ConwayCubes<N> {
+  fn get_active(&self, cube: [i64; N]) -> bool {
+    self.cubes.is_alive(cube)
   }
 
-  fn get_active(&self, x: i64, y: i64, z: i64) -> bool {
-    self.values.get(&(x, y, z)).is_some()
+  fn get_active_count(&self) -> usize {
+    self.cubes.live_count()
   }
 
-  fn get_active_neighbour_count(&self, x: i64, y: i64, z: i64) -> usize {
-    let mut count = 0;
-    for xd in -1..=1 {
-      for yd in -1..=1 {
-        for zd in -1..=1 {
-          if xd != 0 || yd != 0 || zd != 0 {
-            count += self.get_active(x+xd, y+yd, z+zd) as usize;
-          }
-        }
-      }
-    }
-    count
+  fn iterate(&mut self) {
+    self.cubes.step();
   }
 
-  fn get_active_count(&self) -> usize {
-    self.values.len()
-  }
-
-  fn set_active(&mut self, x: i64, y: i64, z: i64) -> bool {
-    if self.empty {
-      self.xrange.set_range(x);
-      self.yrange.set_range(y);
-      self.zrange.set_range(z);
-    } else {
-      self.xrange.absorb(x);
-      self.yrange.absorb(y);
-      self.zrange.absorb(z);
-    }
-    self.empty = false;
-    !self.values.insert((x, y, z))
-  }
-
-  fn iterate(&self) -> Self {
-    let mut next = Dimension3::new();
-    for x in self.xrange.min-1..=self.xrange.max+1 {
-      for y in self.yrange.min-1..=self.yrange.max+1 {
-        for z in self.zrange.min-1..=self.zrange.max+1 {
-          let ncount = self.get_active_neighbour_count(x, y, z);
-          let next_is_active = if self.get_active(x, y, z) {
-            // If active, stay active if 2 or 3 active neighbours.
-            ncount == 2 || ncount == 3
-          } else {
-            // If inactive, become active if 3 active neighbours.
-            ncount == 3
-          };
-          if next_is_active {
-            next.set_active(x, y, z);
-          }
+  // The range of each coordinate that holds active cubes.
+  fn ranges(&self) -> Vec<DimensionRange> {
+    let mut ranges: Vec<DimensionRange> = Vec::new();
+    for cube in self.cubes.live_cells() {
+      if ranges.is_empty() {
+        ranges = (0..N).map(|_| DimensionRange { min: 0, max: 0 }).collect();
+        for (range, &i) in ranges.iter_mut().zip(cube) {
+          range.set_range(i);
         }
       }
+      for (range, &i) in ranges.iter_mut().zip(cube) {
+        range.absorb(i);
+      }
     }
-    next
+    ranges
   }
 
   fn from_str(string: &str) -> Self {
+    assert!(N >= 2, "the starting slice needs at least 2 dimensions");
     let lines: Vec<&str> = string.split_terminator("\n").collect();
-    let mut dimension = Dimension3::new();
+    let mut active = Vec::new();
     for (y, line) in lines.into_iter().enumerate() {
       for (x, c) in line.chars().enumerate() {
         if c == '#' {
-          dimension.set_active(x as i64, y as i64, 0);
+          let mut cube = [0; N];
+          cube[0] = x as i64;
+          cube[1] = y as i64;
+          active.push(cube);
         }
       }
     }
-    dimension
+    ConwayCubes {
+      cubes: Automaton::new(Lattice::new(), conway_rules(), active),
+    }
   }
-}
 
-struct Dimension4 {
-  empty: bool,
-  values: std::collections::HashSet<(i64, i64, i64, i64)>,
-  xrange: DimensionRange,
-  yrange: DimensionRange,
-  zrange: DimensionRange,
-  wrange: DimensionRange,
-}
-impl Dimension4 {
-  fn new() -> Self {
-    Self {
-      empty: true,
-      values: std::collections::HashSet::new(),
-      xrange: DimensionRange { min: 0, max: 0 },
-      yrange: DimensionRange { min: 0, max: 0 },
-      zrange: DimensionRange { min: 0, max: 0 },
-      wrange: DimensionRange { min: 0, max: 0 },
+  // Draws the x-y slice at the given coordinates for the other dimensions
+  // (z, w, ...).
+  fn to_str(&self, slice: &[i64]) -> String {
+    assert_eq!(slice.len(), N - 2);
+    let ranges = self.ranges();
+    let mut s = String::new();
+    if ranges.is_empty() {
+      return s;
+    }
+    let mut cube = [0; N];
+    cube[2..].copy_from_slice(slice);
+    for y in ranges[1].min..=ranges[1].max {
+      for x in ranges[0].min..=ranges[0].max {
+        cube[0] = x;
+        cube[1] = y;
+        s += if self.get_active(cube) { "#" } else { "." }
+      }
+      s += "\n";
     }
+    s
   }
 
-  fn get_active(&self, x: i64, y: i64, z: i64, w: i64) -> bool {
-    self.values.get(&(x, y, z, w)).is_some()
-  }
+  fn print(&self) {
+    let ranges = self.ranges();
+    if ranges.is_empty() {
+      return;
+    }
+    // Every combination of the coordinates past x and y, counting like an
+    // odometer with z changing fastest.
+    let mut slice = ranges[2..].iter().map(|r| r.min).collect::<Vec<_>>();
+    loop {
+      let names = ["z", "w", "v", "u", "t"];
+      let coords = slice
+        .iter()
+        .enumerate()
+        .map(|(i, c)| match names.get(i) {
+          Some(name) => format!("{}={}", name, c),
+          None => format!("d{}={}", i + 2, c),
+        })
+        .collect::<Vec<_>>();
+      println!("{}", coords.join(", "));
+      println!("{}\n", self.to_str(&slice));
 
-  fn get_active_neighbour_count(&self, x: i64, y: i64, z: i64, w:i64) -> usize {
-    let mut count = 0;
-    for xd in -1..=1 {
-      for yd in -1..=1 {
-        for zd in -1..=1 {
-          for wd in -1..=1 {
-            if xd != 0 || yd != 0 || zd != 0 || wd != 0 {
-              count += self.get_active(x+xd, y+yd, z+zd, w+wd) as usize;
-            }
-          }
+      let mut axis = 0;
+      loop {
+        if axis == slice.len() {
+          return;
+        }
+        if slice[axis] < ranges[axis + 2].max {
+          slice[axis] += 1;
+          break;
         }
+        slice[axis] = ranges[axis + 2].min;
+        axis += 1;
       }
     }
-    count
   }
+}
 
-  fn get_active_count(&self) -> usize {
-    self.values.len()
-  }
-
-  fn set_active(&mut self, x: i64, y: i64, z: i64, w: i64) -> bool {
-    if self.empty {
-      self.xrange.set_range(x);
-      self.yrange.set_range(y);
-      self.zrange.set_range(z);
-      self.wrange.set_range(w);
-    } else {
-      self.xrange.absorb(x);
-      self.yrange.absorb(y);
-      self.zrange.absorb(z);
-      self.wrange.absorb(w);
+// Conway cubes in N dimensions, simulated on only a fraction of the cubes.
+//
+// The starting slice sits at 0 in every dimension past x and y, so flipping
+// the sign of any of those coordinates, or swapping any two of them, gives
+// the same pocket dimension, and the rules keep that symmetry every cycle.
+// Each active cube here stands for its whole orbit under those symmetries,
+// and is kept in canonical form, with the extra coordinates made positive
+// and sorted.
+//
+// Neighbour counts come from the active cubes, each adding its orbit size to
+// the canonical form of each of its neighbours. A cube `t` is next to
+// `|orbit(c)| / |orbit(t)|` cubes of `c`'s orbit for each neighbour of `c`
+// that has the canonical form `t`, so dividing the sum by the orbit size of
+// `t` gives its number of active neighbours.
+struct SymmetricCubes<const N: usize> {
+  lattice: Lattice<N>,
+  rules: Rules,
+  // Active cubes in canonical form, with the size of their orbits.
+  active: HashMap<[i64; N], u64>,
+}
+impl<const N: usize> SymmetricCubes<N> {
+  fn from_str(string: &str) -> Self {
+    let starting = ConwayCubes::<N>::from_str(string);
+    SymmetricCubes {
+      lattice: Lattice::new(),
+      rules: conway_rules(),
+      active: starting.cubes.live_cells().map(|&c| (c, 1)).collect(),
     }
-    self.empty = false;
-    !self.values.insert((x, y, z, w))
-  }
-
-  fn iterate(&self) -> Self {
-    let mut next = Dimension4::new();
-    for x in self.xrange.min-1..=self.xrange.max+1 {
-      for y in self.yrange.min-1..=self.yrange.max+1 {
-        for z in self.zrange.min-1..=self.zrange.max+1 {
-          for w in self.wrange.min-1..=self.wrange.max+1 {
-            let ncount = self.get_active_neighbour_count(x, y, z, w);
-            let next_is_active = if self.get_active(x, y, z, w) {
-              // If active, stay active if 2 or 3 active neighbours.
-              ncount == 2 || ncount == 3
-            } else {
-              // If inactive, become active if 3 active neighbours.
-              ncount == 3
-            };
-            if next_is_active {
-              next.set_active(x, y, z, w);
-            }
-          }
-        }
-      }
+  }
+
+  fn canonical(mut cube: [i64; N]) -> [i64; N] {
+    for i in &mut cube[2..] {
+      *i = i.abs();
     }
-    next
+    cube[2..].sort_unstable();
+    cube
   }
 
-  fn from_str(string: &str) -> Self {
-    let lines: Vec<&str> = string.split_terminator("\n").collect();
-    let mut dimension = Dimension4::new();
-    for (y, line) in lines.into_iter().enumerate() {
-      for (x, c) in line.chars().enumerate() {
-        if c == '#' {
-          dimension.set_active(x as i64, y as i64, /*z=*/0, /*w=*/0);
-        }
+  // How many cubes the canonical `cube` stands for: every order of its
+  // extra coordinates, with either sign for those which aren't 0.
+  fn orbit_size(cube: &[i64; N]) -> u64 {
+    let extra = &cube[2..];
+    let mut size = (1..=extra.len() as u64).product::<u64>();
+    let mut run = 1;
+    for i in 1..=extra.len() {
+      if i < extra.len() && extra[i] == extra[i - 1] {
+        run += 1;
+      } else {
+        size /= (1..=run).product::<u64>();
+        run = 1;
       }
     }
-    dimension
+    size << extra.iter().filter(|&&i| i != 0).count()
   }
 
-  fn to_str(&self, z: i64, w: i64) -> String {
-    let mut s = String::new();
-    for y in self.yrange.min..=self.yrange.max {
-      for x in self.xrange.min..=self.xrange.max {
-        s += if self.get_active(x, y, z, w) { "#" } else { "." }
-      }
-      s += "\n";
+  fn get_active_count(&self) -> usize {
+    self.active.values().sum::<u64>() as usize
+  }
+
+  fn iterate(&mut self) {
+    let mut weighted = HashMap::<[i64; N], u64>::default();
+    for (&cube, &orbit) in &self.active {
+      self.lattice.for_each_neighbour(cube, |n| {
+        *weighted.entry(Self::canonical(n)).or_insert(0) += orbit;
+      });
     }
-    s
+    // Cubes with no active neighbours are inactive next cycle.
+    let next = weighted
+      .into_iter()
+      .filter_map(|(cube, weight)| {
+        let orbit = Self::orbit_size(&cube);
+        let alive = self.active.contains_key(&cube);
+        if self.rules.next_state(alive, (weight / orbit) as usize) {
+          Some((cube, orbit))
+        } else {
+          None
+        }
+      })
+      .collect();
+    self.active = next;
   }
 
-  #[allow(dead_code)]
-  fn print(&self) {
-    for w in self.wrange.min..=self.wrange.max {
-      for z in self.zrange.min..=self.zrange.max {
-        println!("z={}, w={}", z, w);
-        println!("{}\n", self.to_str(/*z=*/z, /*w=*/w));
+  // Every active cube, expanded from the canonical ones.
+  #[cfg(test)]
+  fn active_cubes(&self) -> Vec<[i64; N]> {
+    let mut cubes = Vec::new();
+    for &cube in self.active.keys() {
+      let mut orbit = vec![cube];
+      for axis in 2..N {
+        // Swap the coordinate into every place after it, then flip its sign.
+        let swapped = orbit
+          .iter()
+          .flat_map(|&c| {
+            (axis..N).map(move |other| {
+              let mut c = c;
+              c.swap(axis, other);
+              c
+            })
+          })
+          .collect::<Vec<_>>();
+        orbit = swapped
+          .into_iter()
+          .flat_map(|c| {
+            let mut flipped = c;
+            flipped[axis] = -flipped[axis];
+            vec![c, flipped]
+          })
+          .collect();
       }
+      orbit.sort_unstable();
+      orbit.dedup();
+      cubes.extend(orbit);
     }
-    }
+    cubes.sort_unstable();
+    cubes
+  }
 }
 
-fn p1(mut dimension: Dimension3) -> anyhow::Result<()> {
-  for _ in 0..6 {
-    dimension = dimension.iterate();
+// Runs the boot process for `cycles` cycles, returning how many cubes are
+// left active.
+fn boot<const N: usize>(input_all: &str, cycles: usize) -> usize {
+  let mut dimension = SymmetricCubes::<N>::from_str(input_all);
+  for _ in 0..cycles {
+    dimension.iterate();
   }
-  println!("Part 1 {}", dimension.get_active_count());
-  Ok(())
+  dimension.get_active_count()
 }
 
-fn p2(mut dimension: Dimension4) -> anyhow::Result<()> {
-  for _ in 0..6 {
-    dimension = dimension.iterate();
+// The same as `boot`, looking at every cube, and optionally printing the
+// slices at the end.
+fn boot_naive<const N: usize>(input_all: &str, cycles: usize, show: bool) -> usize {
+  let mut dimension = ConwayCubes::<N>::from_str(input_all);
+  for _ in 0..cycles {
+    dimension.iterate();
   }
-  println!("Part 2 {}", dimension.get_active_count());
-  Ok(())
+  if show {
+    dimension.print();
+  }
+  dimension.get_active_count()
 }
 
+const BOOT_CYCLES: usize = 6;
+
 fn main() -> anyhow::Result<()> {
   let input_all = read_input()?;
-  p1(Dimension3::from_str(&input_all))?;
-  p2(Dimension4::from_str(&input_all))?;
+
+  // `day17 DIMENSIONS [CYCLES] [naive|print]` boots in any number of
+  // dimensions from 2 to 7. With `naive` it simulates every cube instead of
+  // using symmetry, and with `print` it also prints the slices at the end.
+  let args = std::env::args().skip(1).collect::<Vec<_>>();
+  if let Some(dims) = args.first() {
+    let cycles = match args.get(1).filter(|a| *a != "naive" && *a != "print") {
+      Some(c) => c.parse()?,
+      None => BOOT_CYCLES,
+    };
+    let show = args.iter().any(|a| a == "print");
+    let naive = show || args.iter().any(|a| a == "naive");
+    let active = match (dims.parse::<usize>()?, naive) {
+      (2, false) => boot::<2>(&input_all, cycles),
+      (3, false) => boot::<3>(&input_all, cycles),
+      (4, false) => boot::<4>(&input_all, cycles),
+      (5, false) => boot::<5>(&input_all, cycles),
+      (6, false) => boot::<6>(&input_all, cycles),
+      (7, false) => boot::<7>(&input_all, cycles),
+      (2, true) => boot_naive::<2>(&input_all, cycles, show),
+      (3, true) => boot_naive::<3>(&input_all, cycles, show),
+      (4, true) => boot_naive::<4>(&input_all, cycles, show),
+      (5, true) => boot_naive::<5>(&input_all, cycles, show),
+      (6, true) => boot_naive::<6>(&input_all, cycles, show),
+      (7, true) => boot_naive::<7>(&input_all, cycles, show),
+      (n, _) => return Err(anyhow!("{} dimensions is not supported, expected 2 to 7", n)),
+    };
+    println!("{} dimensions, {} cycles: {}", dims, cycles, active);
+    return Ok(());
+  }
+
+  println!("Part 1 {}", boot::<3>(&input_all, BOOT_CYCLES));
+  println!("Part 2 {}", boot::<4>(&input_all, BOOT_CYCLES));
   Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const TEST_INPUT: &str = ".#.\n..#\n###\n";
+
+  #[test]
+  fn test_example() {
+    let mut dimension = ConwayCubes::<3>::from_str(TEST_INPUT);
+    assert_eq!(dimension.to_str(&[0]), TEST_INPUT);
+    dimension.iterate();
+    assert_eq!(dimension.get_active_count(), 11);
+    assert_eq!(dimension.to_str(&[-1]), "#..\n..#\n.#.\n");
+    for _ in 1..6 {
+      dimension.iterate();
+    }
+    assert_eq!(dimension.get_active_count(), 112);
+
+    let dimension = ConwayCubes::<4>::from_str(TEST_INPUT);
+    assert_eq!(dimension.to_str(&[0, 0]), TEST_INPUT);
+    assert_eq!(boot_naive::<4>(TEST_INPUT, BOOT_CYCLES, false), 848);
+
+    // In 2 dimensions this is the game of life, where the glider keeps its
+    // 5 cells.
+    assert_eq!(boot_naive::<2>(TEST_INPUT, 4, false), 5);
+  }
+
+  // Checks the symmetric simulation has the same active cubes as the naive
+  // one after each cycle.
+  fn assert_same<const N: usize>(input: &str, cycles: usize) {
+    let mut naive = ConwayCubes::<N>::from_str(input);
+    let mut symmetric = SymmetricCubes::<N>::from_str(input);
+    for _ in 0..cycles {
+      naive.iterate();
+      symmetric.iterate();
+      let mut naive_cubes = naive.cubes.live_cells().copied().collect::<Vec<_>>();
+      naive_cubes.sort_unstable();
+      assert_eq!(symmetric.active_cubes(), naive_cubes);
+      assert_eq!(symmetric.get_active_count(), naive.get_active_count());
+    }
+  }
+
+  #[test]
+  fn test_symmetric() {
+    assert_eq!(boot::<3>(TEST_INPUT, BOOT_CYCLES), 112);
+    assert_eq!(boot::<4>(TEST_INPUT, BOOT_CYCLES), 848);
+    assert_same::<2>(TEST_INPUT, 4);
+    assert_same::<3>(TEST_INPUT, BOOT_CYCLES);
+    assert_same::<4>(TEST_INPUT, BOOT_CYCLES);
+    assert_same::<5>(TEST_INPUT, 3);
+    assert_same::<4>("##.#\n.##.\n#..#\n####\n", 4);
+
+    // [0, 0, 2, 2, 0] stands for every order of (0, 2, 2) with either sign
+    // on each 2.
+    assert_eq!(SymmetricCubes::<5>::orbit_size(&[0, 0, 0, 2, 2]), 3 * 4);
+    assert_eq!(SymmetricCubes::<5>::orbit_size(&[0, 0, 0, 1, 2]), 6 * 4);
+    assert_eq!(SymmetricCubes::<5>::orbit_size(&[0, 0, 0, 0, 0]), 1);
+    assert_eq!(SymmetricCubes::<5>::canonical([1, -1, 2, -3, 0]), [1, -1, 0, 2, 3]);
+  }
+}
@@ -6,6 +6,10 @@ extern crate anyhow;
 //use regex::Regex;
 use std::collections::HashMap;
 
+#[path = "../automaton/automaton.rs"]
+mod automaton;
+use automaton::{Automaton, HexGrid, Rules};
+
 // Directions:
 //
 //  NW /\ NE
@@ -90,52 +94,19 @@ impl Floor {
 
   fn flip_tile(&mut self, x: isize, y: isize) {
     self.tiles.entry((x, y)).or_insert(Tile::new()).flip();
-    for (nx, ny) in [(x-2, y), (x-1, y+1), (x+1, y+1), (x+2, y), (x+1, y-1), (x-1, y-1)].iter() {
-      self.tiles.entry((*nx, *ny)).or_insert(Tile::new());
-    }
     //println!("Flip ({}, {}) to {:?}", x, y, self.tiles.get(&(x, y)).unwrap().face);
   }
 
-  fn count_neighbours(&self, x: isize, y: isize, face: Side) -> usize {
-    let mut count = 0;
-    for (nx, ny) in [(x-2, y), (x-1, y+1), (x+1, y+1), (x+2, y), (x+1, y-1), (x-1, y-1)].iter() {
-      if let Some(tile) = self.tiles.get(&(*nx, *ny)) {
-        if face == tile.face {
-          count += 1;
-        }
-      } else {
-        // Not flipped ever, so white.
-        if face == Side::White {
-          count += 1;
-        }
-      }
-    }
-    count
-  }
-
-  fn step_game(&mut self) {
-    let mut to_flip: Vec<(isize, isize)> = Vec::new();
-
-    for ((x, y), tile) in &self.tiles {
-      match tile.face {
-        Side::Black => {
-          let n = self.count_neighbours(*x, *y, Side::Black);
-          if n == 0 || n > 2 {
-            to_flip.push((*x, *y));
-          }
-        },
-        Side::White => {
-          let n = self.count_neighbours(*x, *y, Side::Black);
-          if n == 2 {
-            to_flip.push((*x, *y));
-          }
-        },
-      }
-    }
-
-    for (x, y) in to_flip {
-      self.flip_tile(x, y);
-    }
+  // The living art exhibit, where black tiles are alive. A black tile with
+  // zero or more than 2 black neighbours flips to white, and a white tile with
+  // exactly 2 black neighbours flips to black.
+  fn into_exhibit(self) -> Automaton<HexGrid> {
+    let black = self
+      .tiles
+      .into_iter()
+      .filter(|(_, tile)| tile.face == Side::Black)
+      .map(|(pos, _)| pos);
+    Automaton::new(HexGrid, Rules::new(2..=2, 1..=2), black)
   }
 
   fn count_tiles(&self, face: Side) -> usize {
@@ -156,11 +127,9 @@ fn solve(input_all: String) -> anyhow::Result<()> {
   }
   println!("Part 1 {}", floor.count_tiles(Side::Black));
 
-  for _day in 1..=100 {
-    floor.step_game();
-    //println!("Day {}: {}", _day, floor.count_tiles(Side::Black));
-  }
-  println!("Part 2 {}", floor.count_tiles(Side::Black));
+  let mut exhibit = floor.into_exhibit();
+  exhibit.run(100);
+  println!("Part 2 {}", exhibit.live_count());
 
   Ok(())
 }
@@ -173,3 +142,22 @@ fn main() -> anyhow::Result<()> {
   };
   solve(input_all)
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_example() {
+    let mut floor = Floor::new();
+    for line in include_str!("test.txt").lines() {
+      floor.walk_and_flip(line);
+    }
+    assert_eq!(floor.count_tiles(Side::Black), 10);
+
+    let mut exhibit = floor.into_exhibit();
+    assert_eq!(exhibit.live_count(), 10);
+    exhibit.run(100);
+    assert_eq!(exhibit.live_count(), 2208);
+  }
+}
@@ -1,21 +1,39 @@
 #[macro_use]
+extern crate anyhow;
+#[macro_use]
 extern crate lazy_static;
 extern crate regex;
 use regex::Regex;
 
 static INPUT_FILE: &str = "day8/input.txt";
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 enum Operation {
   Nop,
   Acc,
   Jmp,
 }
+impl std::fmt::Display for Operation {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    let s = match self {
+      Operation::Nop => "nop",
+      Operation::Acc => "acc",
+      Operation::Jmp => "jmp",
+    };
+    write!(f, "{}", s)
+  }
+}
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 struct Instruction {
   op: Operation,
   arg: i64,
 }
+impl std::fmt::Display for Instruction {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{} {:+}", self.op, self.arg)
+  }
+}
 
 fn read_operation(s: &str) -> Operation {
   if s == "nop" {
@@ -52,10 +70,194 @@ fn add_signed(u: usize, i: i64) -> usize {
   }
 }
 
-fn main() {
-  let input_all = std::fs::read_to_string(INPUT_FILE).unwrap();
-  p1(&input_all);
-  p2(&input_all);
+// Assembles a program written in the puzzle format, but where any line may
+// be prefixed by a `label:` and the argument of `nop` or `jmp` may be a label
+// name instead of a signed offset. Labels are lowered to the relative offset
+// from the instruction to the labelled line. Blank lines and `#` comments are
+// ignored, and a label on a line of its own refers to the next instruction (or
+// to one past the end of the program).
+fn assemble(source: &str) -> anyhow::Result<Vec<Instruction>> {
+  lazy_static! {
+    static ref LABEL_RE: Regex = Regex::new(r"^([A-Za-z_][A-Za-z0-9_]*):\s*(.*)$").unwrap();
+    static ref INST_RE: Regex =
+      Regex::new(r"^(nop|acc|jmp)\s+([+-]?[0-9]+|[A-Za-z_][A-Za-z0-9_]*)$").unwrap();
+  };
+
+  // First pass: find the line each label points at, and collect the
+  // (operation, argument text) pairs to lower.
+  let mut labels = std::collections::HashMap::<&str, usize>::new();
+  let mut unresolved: Vec<(usize, Operation, &str)> = Vec::new();
+  for (line_num, line) in source.split_terminator("\n").enumerate() {
+    let mut line = line.split('#').next().unwrap().trim();
+    while let Some(captures) = LABEL_RE.captures(line) {
+      let label = captures.get(1).unwrap().as_str();
+      if labels.insert(label, unresolved.len()).is_some() {
+        return Err(anyhow!("line {}: duplicate label `{}`", line_num + 1, label));
+      }
+      line = captures.get(2).unwrap().as_str();
+    }
+    if line.is_empty() {
+      continue;
+    }
+    let captures = INST_RE
+      .captures(line)
+      .ok_or_else(|| anyhow!("line {}: invalid instruction `{}`", line_num + 1, line))?;
+    let op = read_operation(captures.get(1).unwrap().as_str());
+    unresolved.push((line_num, op, captures.get(2).unwrap().as_str()));
+  }
+
+  // Second pass: resolve label arguments into relative offsets.
+  let mut program = Vec::with_capacity(unresolved.len());
+  for (pc, (line_num, op, arg_str)) in unresolved.into_iter().enumerate() {
+    let arg = if let Ok(arg) = arg_str.parse::<i64>() {
+      arg
+    } else {
+      if op == Operation::Acc {
+        return Err(anyhow!("line {}: acc can not take a label", line_num + 1));
+      }
+      let target = labels
+        .get(arg_str)
+        .ok_or_else(|| anyhow!("line {}: unknown label `{}`", line_num + 1, arg_str))?;
+      *target as i64 - pc as i64
+    };
+    program.push(Instruction { op, arg });
+  }
+  Ok(program)
+}
+
+// Writes a program back out in the puzzle format, one instruction per line.
+fn disassemble(program: &[Instruction]) -> String {
+  program.iter().map(|inst| format!("{}\n", inst)).collect()
+}
+
+// A single executed instruction. The `acc` is the accumulator value after the
+// instruction ran.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct TraceStep {
+  pc: usize,
+  op: Operation,
+  acc: i64,
+}
+impl std::fmt::Display for TraceStep {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{:>5}: {} acc={}", self.pc, self.op, self.acc)
+  }
+}
+
+// How an execution of a program came to a stop.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TraceEnd {
+  // The program ran to one past its last instruction.
+  Terminated,
+  // The instruction at `steps[cycle_start].pc` was about to run a second time.
+  Looped { cycle_start: usize },
+  // A jmp went somewhere other than a line of the program or one past its end.
+  OutOfBounds { target: i64 },
+}
+
+struct Trace {
+  steps: Vec<TraceStep>,
+  end: TraceEnd,
+}
+impl Trace {
+  // The accumulator value at the point the program stopped.
+  fn acc(&self) -> i64 {
+    self.steps.last().map_or(0, |step| step.acc)
+  }
+
+  // The steps that make up the infinite loop, if the program looped.
+  fn cycle(&self) -> Option<&[TraceStep]> {
+    match self.end {
+      TraceEnd::Looped { cycle_start } => Some(&self.steps[cycle_start..]),
+      _ => None,
+    }
+  }
+}
+
+// Executes the program, recording every instruction that runs, until it
+// terminates, would run an instruction for the second time, or jumps out of
+// bounds.
+fn trace(program: &[Instruction]) -> Trace {
+  // For each line, the index into `steps` where it was first run.
+  let mut visited: Vec<Option<usize>> = vec![None; program.len()];
+  let mut steps = Vec::new();
+  let mut accumulator: i64 = 0;
+  let mut pc: usize = 0;
+
+  let end = loop {
+    if pc == program.len() {
+      break TraceEnd::Terminated;
+    }
+    if let Some(cycle_start) = visited[pc] {
+      break TraceEnd::Looped { cycle_start };
+    }
+    visited[pc] = Some(steps.len());
+
+    let instruction = &program[pc];
+    let next_pc = match instruction.op {
+      Operation::Nop => pc as i64 + 1,
+      Operation::Acc => {
+        accumulator += instruction.arg;
+        pc as i64 + 1
+      }
+      Operation::Jmp => pc as i64 + instruction.arg,
+    };
+    steps.push(TraceStep {
+      pc,
+      op: instruction.op,
+      acc: accumulator,
+    });
+    if next_pc < 0 || next_pc > program.len() as i64 {
+      break TraceEnd::OutOfBounds { target: next_pc };
+    }
+    pc = next_pc as usize;
+  };
+  Trace { steps, end }
+}
+
+fn print_trace(program: &[Instruction]) {
+  let t = trace(program);
+  for step in &t.steps {
+    println!("{}", step);
+  }
+  match t.end {
+    TraceEnd::Terminated => println!("Terminated with acc={}", t.acc()),
+    TraceEnd::OutOfBounds { target } => println!("Jumped out of bounds to {}", target),
+    TraceEnd::Looped { .. } => {
+      println!("Infinite loop:");
+      for step in t.cycle().unwrap() {
+        println!("{}", step);
+      }
+    }
+  }
+}
+
+fn main() -> anyhow::Result<()> {
+  // `day8 trace [FILE]` traces a program in the puzzle format, `day8 analyse
+  // [FILE]` and `day8 dot [FILE]` report on its control flow, and `day8 asm
+  // FILE` assembles a labelled program and traces it.
+  let args = std::env::args().collect::<Vec<_>>();
+  let read_program = || -> anyhow::Result<Vec<Instruction>> {
+    let input_all = std::fs::read_to_string(args.get(2).map_or(INPUT_FILE, |s| s.as_str()))?;
+    Ok(input_all.split_terminator("\n").map(read_instruction).collect())
+  };
+  match args.get(1).map(|s| s.as_str()) {
+    Some("trace") => print_trace(&read_program()?),
+    Some("analyse") => print!("{}", analyse(&read_program()?)),
+    Some("dot") => print!("{}", to_dot(&read_program()?)),
+    Some("asm") => {
+      let file = args.get(2).ok_or_else(|| anyhow!("asm needs a source file"))?;
+      let program = assemble(&std::fs::read_to_string(file)?)?;
+      print!("{}", disassemble(&program));
+      print_trace(&program);
+    }
+    _ => {
+      let input_all = std::fs::read_to_string(INPUT_FILE)?;
+      p1(&input_all);
+      p2(&input_all)?;
+    }
+  }
+  Ok(())
 }
 
 fn p1(input_all: &str) {
@@ -97,7 +299,347 @@ fn p1(input_all: &str) {
   println!("Part 1 {}", accumulator);
 }
 
-fn p2(input_all: &str) {
+// The single nop/jmp flip that makes a program terminate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Repair {
+  // The line whose operation was flipped.
+  line: usize,
+  // The accumulator once the repaired program terminates.
+  acc: i64,
+}
+
+fn flip(op: Operation) -> Operation {
+  match op {
+    Operation::Nop => Operation::Jmp,
+    Operation::Jmp => Operation::Nop,
+    Operation::Acc => Operation::Acc,
+  }
+}
+
+// The line run after `pc` if it executes `op` with `arg`, where `len` (one past
+// the end of the program) means termination. None for jumps out of bounds.
+fn successor(pc: usize, op: Operation, arg: i64, len: usize) -> Option<usize> {
+  let next_pc = match op {
+    Operation::Nop | Operation::Acc => pc as i64 + 1,
+    Operation::Jmp => pc as i64 + arg,
+  };
+  if next_pc < 0 || next_pc > len as i64 {
+    None
+  } else {
+    Some(next_pc as usize)
+  }
+}
+
+// The control-flow graph of a program. Every line has at most one successor,
+// and the node at index `len` is the termination point one past the end of the
+// program.
+struct ControlFlowGraph {
+  len: usize,
+  // The next line to run after each line, or None when it jumps out of bounds.
+  succs: Vec<Option<usize>>,
+  // The lines that lead to each node, including the termination point.
+  preds: Vec<Vec<usize>>,
+}
+impl ControlFlowGraph {
+  fn new(program: &[Instruction]) -> Self {
+    let len = program.len();
+    let succs = program
+      .iter()
+      .enumerate()
+      .map(|(pc, inst)| successor(pc, inst.op, inst.arg, len))
+      .collect::<Vec<_>>();
+    let mut preds: Vec<Vec<usize>> = vec![Vec::new(); len + 1];
+    for (pc, succ) in succs.iter().enumerate() {
+      if let Some(next_pc) = succ {
+        preds[*next_pc].push(pc);
+      }
+    }
+    ControlFlowGraph { len, succs, preds }
+  }
+
+  // Marks every node run when executing from the first line.
+  fn reachable_from_start(&self) -> Vec<bool> {
+    let mut reachable = vec![false; self.len + 1];
+    let mut pc = Some(0);
+    while let Some(p) = pc {
+      if reachable[p] {
+        break;
+      }
+      reachable[p] = true;
+      pc = self.succs.get(p).copied().flatten();
+    }
+    reachable
+  }
+
+  // Marks every node from which the program runs to termination, by walking
+  // the graph backward from one past the end of the program.
+  fn reaches_end(&self) -> Vec<bool> {
+    let mut reaches_end = vec![false; self.len + 1];
+    reaches_end[self.len] = true;
+    let mut stack = vec![self.len];
+    while let Some(pc) = stack.pop() {
+      for &pred in &self.preds[pc] {
+        if !reaches_end[pred] {
+          reaches_end[pred] = true;
+          stack.push(pred);
+        }
+      }
+    }
+    reaches_end
+  }
+
+  // Every cycle in the graph, each given as its lines in execution order
+  // starting from the lowest line number.
+  fn cycles(&self) -> Vec<Vec<usize>> {
+    // The walk (by starting line + 1) that first visited each line, or 0.
+    let mut walked_by = vec![0; self.len];
+    let mut cycles = Vec::new();
+    for start in 0..self.len {
+      let mut walk = Vec::new();
+      let mut pc = Some(start);
+      while let Some(p) = pc.filter(|&p| p < self.len) {
+        if walked_by[p] != 0 {
+          // Only a line from this walk closes a new cycle.
+          if walked_by[p] == start + 1 {
+            let at = walk.iter().position(|&w| w == p).unwrap();
+            cycles.push(walk.split_off(at));
+          }
+          break;
+        }
+        walked_by[p] = start + 1;
+        walk.push(p);
+        pc = self.succs[p];
+      }
+    }
+    for cycle in &mut cycles {
+      let lowest = cycle.iter().enumerate().min_by_key(|(_, &pc)| pc).unwrap().0;
+      cycle.rotate_left(lowest);
+    }
+    cycles
+  }
+}
+
+// Finds the nop/jmp to flip so the program terminates, in linear time. Only
+// lines run by the unmodified program can change its behaviour when flipped,
+// and flipping one of them fixes the program exactly when its new successor
+// is a line that already reaches the end.
+//
+// That only holds for a program that doesn't terminate, since otherwise the
+// path to the end from the new successor can run back through the flipped
+// line. A program that already terminates needs no repair, so gives None.
+fn repair(program: &[Instruction]) -> Option<Repair> {
+  let len = program.len();
+  let t = trace(program);
+  if t.end == TraceEnd::Terminated {
+    return None;
+  }
+  let reaches_end = ControlFlowGraph::new(program).reaches_end();
+  let line = t.steps.iter().map(|step| step.pc).find(|&pc| {
+    let inst = &program[pc];
+    inst.op != Operation::Acc
+      && successor(pc, flip(inst.op), inst.arg, len).is_some_and(|next_pc| reaches_end[next_pc])
+  })?;
+
+  let mut repaired = program.to_vec();
+  repaired[line].op = flip(repaired[line].op);
+  let t = trace(&repaired);
+  if t.end != TraceEnd::Terminated {
+    return None;
+  }
+  Some(Repair { line, acc: t.acc() })
+}
+
+// A single-instruction change that makes the program terminate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Patch {
+  line: usize,
+  from: Instruction,
+  to: Instruction,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct InfiniteLoop {
+  lines: Vec<usize>,
+  // Whether executing the program from the start gets stuck in this loop.
+  reached_from_start: bool,
+}
+
+// The result of statically analysing a program without running it.
+struct Analysis {
+  len: usize,
+  terminates: bool,
+  unreachable: Vec<usize>,
+  loops: Vec<InfiniteLoop>,
+  // Pairs of (line, target) for jumps outside of the program.
+  out_of_bounds: Vec<(usize, i64)>,
+  // Flips of a nop/jmp that make the program terminate. Empty when the program
+  // already terminates.
+  patches: Vec<Patch>,
+}
+
+fn analyse(program: &[Instruction]) -> Analysis {
+  let len = program.len();
+  let cfg = ControlFlowGraph::new(program);
+  let reachable = cfg.reachable_from_start();
+  let reaches_end = cfg.reaches_end();
+  let terminates = reachable[len];
+
+  let unreachable = (0..len).filter(|&pc| !reachable[pc]).collect();
+  let loops = cfg
+    .cycles()
+    .into_iter()
+    .map(|lines| InfiniteLoop {
+      reached_from_start: reachable[lines[0]],
+      lines,
+    })
+    .collect();
+  let out_of_bounds = (0..len)
+    .filter(|&pc| cfg.succs[pc].is_none())
+    .map(|pc| (pc, pc as i64 + program[pc].arg))
+    .collect();
+
+  // Only lines that run can change the program's behaviour when patched, and
+  // with a flip in place the rest of the graph is unchanged, so the new
+  // successor has to already reach the end.
+  let mut patches = Vec::new();
+  if !terminates {
+    for pc in (0..len).filter(|&pc| reachable[pc]) {
+      let from = program[pc];
+      if from.op == Operation::Acc {
+        continue;
+      }
+      let to = Instruction {
+        op: flip(from.op),
+        arg: from.arg,
+      };
+      if successor(pc, to.op, to.arg, len).is_some_and(|next_pc| reaches_end[next_pc]) {
+        patches.push(Patch { line: pc, from, to });
+      }
+    }
+  }
+
+  Analysis {
+    len,
+    terminates,
+    unreachable,
+    loops,
+    out_of_bounds,
+    patches,
+  }
+}
+
+impl std::fmt::Display for Analysis {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    writeln!(f, "Lines: {}", self.len)?;
+    writeln!(f, "Terminates: {}", if self.terminates { "yes" } else { "no" })?;
+    if self.unreachable.is_empty() {
+      writeln!(f, "Unreachable lines: none")?;
+    } else {
+      let lines = self.unreachable.iter().map(|pc| pc.to_string()).collect::<Vec<_>>();
+      writeln!(f, "Unreachable lines: {}", lines.join(", "))?;
+    }
+    if self.loops.is_empty() {
+      writeln!(f, "Infinite loops: none")?;
+    } else {
+      writeln!(f, "Infinite loops:")?;
+      for l in &self.loops {
+        let lines = l.lines.iter().map(|pc| pc.to_string()).collect::<Vec<_>>();
+        let reached = if l.reached_from_start { " (reached from start)" } else { "" };
+        writeln!(f, "  {} -> {}{}", lines.join(" -> "), l.lines[0], reached)?;
+      }
+    }
+    if self.out_of_bounds.is_empty() {
+      writeln!(f, "Out of bounds jumps: none")?;
+    } else {
+      writeln!(f, "Out of bounds jumps:")?;
+      for (pc, target) in &self.out_of_bounds {
+        writeln!(f, "  line {} jumps to {}", pc, target)?;
+      }
+    }
+    if !self.terminates {
+      if self.patches.is_empty() {
+        writeln!(f, "Patches that make the program terminate: none")?;
+      } else {
+        writeln!(f, "Patches that make the program terminate:")?;
+        for patch in &self.patches {
+          writeln!(f, "  line {}: {} -> {}", patch.line, patch.from, patch.to)?;
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+// Renders the control-flow graph in Graphviz DOT format. Unreachable lines are
+// grey, the loop the program gets stuck in is red, jumps out of bounds lead to
+// a red node and patches that fix the program are dashed green edges.
+fn to_dot(program: &[Instruction]) -> String {
+  use std::fmt::Write;
+
+  let len = program.len();
+  let cfg = ControlFlowGraph::new(program);
+  let analysis = analyse(program);
+  let reachable = cfg.reachable_from_start();
+  let mut stuck_in_loop = vec![false; len];
+  for l in analysis.loops.iter().filter(|l| l.reached_from_start) {
+    for &pc in &l.lines {
+      stuck_in_loop[pc] = true;
+    }
+  }
+
+  let mut out = String::new();
+  writeln!(out, "digraph program {{").unwrap();
+  writeln!(out, "  node [shape=box, fontname=monospace];").unwrap();
+  writeln!(out, "  end [label=\"end\", shape=doublecircle];").unwrap();
+  for (pc, inst) in program.iter().enumerate() {
+    let style = if !reachable[pc] {
+      ", color=grey, fontcolor=grey"
+    } else if stuck_in_loop[pc] {
+      ", color=red"
+    } else {
+      ""
+    };
+    writeln!(out, "  l{} [label=\"{}: {}\"{}];", pc, pc, inst, style).unwrap();
+  }
+  for (pc, succ) in cfg.succs.iter().enumerate() {
+    match succ {
+      Some(next_pc) if *next_pc == len => writeln!(out, "  l{} -> end;", pc).unwrap(),
+      Some(next_pc) => {
+        let style = if stuck_in_loop[pc] { " [color=red]" } else { "" };
+        writeln!(out, "  l{} -> l{}{};", pc, next_pc, style).unwrap()
+      }
+      None => {
+        writeln!(out, "  oob{} [label=\"out of bounds\", color=red];", pc).unwrap();
+        writeln!(out, "  l{} -> oob{} [color=red];", pc, pc).unwrap();
+      }
+    }
+  }
+  for patch in &analysis.patches {
+    let next_pc = successor(patch.line, patch.to.op, patch.to.arg, len).unwrap();
+    let target = if next_pc == len { "end".to_string() } else { format!("l{}", next_pc) };
+    writeln!(
+      out,
+      "  l{} -> {} [style=dashed, color=green, label=\"{}\"];",
+      patch.line, target, patch.to.op
+    )
+    .unwrap();
+  }
+  writeln!(out, "}}").unwrap();
+  out
+}
+
+fn p2(input_all: &str) -> anyhow::Result<()> {
+  let program = input_all.split_terminator("\n").map(read_instruction).collect::<Vec<_>>();
+  let result = repair(&program)
+    .ok_or_else(|| anyhow!("The program already terminates, or no single flip repairs it"))?;
+  println!("Part 2 {} (flipped line {})", result.acc, result.line);
+  Ok(())
+}
+
+// The original approach to part 2, which re-executes the program once for
+// each nop/jmp it tries flipping. Kept to cross-check `repair()`.
+#[cfg(test)]
+fn repair_brute_force(input_all: &str) -> Repair {
   struct ProgramLine {
     // Was this line executed yet in the current execution.
     visited: bool,
@@ -119,14 +661,14 @@ fn p2(input_all: &str) {
 
   // Exceute the program over and over, trying to flip a single instruction
   // each time. Once successful termination happens, the loop will resolve
-  // to the accumulator value at the end of that execution.
-  let result = 'execute_program: loop {
+  // to the flipped line and the accumulator value at the end of that execution.
+  'execute_program: loop {
     // Global accumulator for the program to write to.
     let mut accumulator: i64 = 0;
     // Line of the `program` that is running.
     let mut pc: usize = 0;
-    // Becomes true once a nop/jmp has been flipped for this execution.
-    let mut flipped_an_op = false;
+    // Set once a nop/jmp has been flipped for this execution.
+    let mut flipped_line: Option<usize> = None;
     // Reset visited for each execution of the program.
     for pline in &mut program {
       pline.visited = false;
@@ -138,7 +680,10 @@ fn p2(input_all: &str) {
       // Successful program termination if we reach 1 past the end of the program,
       // so stop trying to execute the program and return the accumulator.
       if pc == program.len() {
-        break 'execute_program accumulator;
+        break 'execute_program Repair {
+          line: flipped_line.unwrap(),
+          acc: accumulator,
+        };
       }
 
       let pline = &mut program[pc];
@@ -151,18 +696,18 @@ fn p2(input_all: &str) {
 
       // Try flip an instruction if we haven't tried flipping this instruction
       // and we haven't flipped any other instruction on this exectution.
-      let op_to_run = if flipped_an_op || pline.op_flipped {
+      let op_to_run = if flipped_line.is_some() || pline.op_flipped {
         pline.instruction.op
       } else {
         match pline.instruction.op {
           Operation::Nop => {
             pline.op_flipped = true;
-            flipped_an_op = true;
+            flipped_line = Some(pc);
             Operation::Jmp
           }
           Operation::Jmp => {
             pline.op_flipped = true;
-            flipped_an_op = true;
+            flipped_line = Some(pc);
             Operation::Nop
           }
           Operation::Acc => Operation::Acc, // No flip.
@@ -182,7 +727,139 @@ fn p2(input_all: &str) {
         }
       }
     }
-  };
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const TEST_INPUT: &str = r#"nop +0
+acc +1
+jmp +4
+acc +3
+jmp -3
+acc -99
+acc +1
+jmp -4
+acc +6
+"#;
 
-  println!("Part 2 {}", result);
+  const TEST_SOURCE: &str = r#"# Same as TEST_INPUT, but with labels.
+      nop +0
+top:  acc +1
+      jmp skip
+back: acc +3
+      jmp top
+      acc -99
+skip:
+      acc +1
+      jmp back  # Loops forever.
+      acc +6
+"#;
+
+  fn read_program(input: &str) -> Vec<Instruction> {
+    input.split_terminator("\n").map(read_instruction).collect()
+  }
+
+  #[test]
+  fn test_assemble() -> anyhow::Result<()> {
+    let program = assemble(TEST_SOURCE)?;
+    assert_eq!(program, read_program(TEST_INPUT));
+    assert_eq!(disassemble(&program), TEST_INPUT);
+    assert_eq!(assemble(&disassemble(&program))?, program);
+
+    assert!(assemble("jmp nowhere").is_err());
+    assert!(assemble("a: nop +0\na: nop +0").is_err());
+    assert!(assemble("end: acc end").is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_trace() {
+    let t = trace(&read_program(TEST_INPUT));
+    assert_eq!(t.end, TraceEnd::Looped { cycle_start: 1 });
+    assert_eq!(t.acc(), 5);
+    let cycle_pcs = t.cycle().unwrap().iter().map(|step| step.pc).collect::<Vec<_>>();
+    assert_eq!(cycle_pcs, vec![1, 2, 6, 7, 3, 4]);
+
+    let t = trace(&assemble("acc +2\njmp +2\nacc +5\nacc +1").unwrap());
+    assert_eq!(t.end, TraceEnd::Terminated);
+    assert_eq!(t.acc(), 3);
+
+    let t = trace(&assemble("nop +0\njmp -2").unwrap());
+    assert_eq!(t.end, TraceEnd::OutOfBounds { target: -1 });
+  }
+
+  #[test]
+  fn test_analyse() -> anyhow::Result<()> {
+    let a = analyse(&read_program(TEST_INPUT));
+    assert!(!a.terminates);
+    assert_eq!(a.unreachable, vec![5, 8]);
+    assert_eq!(
+      a.loops,
+      vec![InfiniteLoop {
+        lines: vec![1, 2, 6, 7, 3, 4],
+        reached_from_start: true
+      }]
+    );
+    assert!(a.out_of_bounds.is_empty());
+    let patched_lines = a.patches.iter().map(|p| p.line).collect::<Vec<_>>();
+    assert_eq!(patched_lines, vec![7]);
+    assert_eq!(a.patches[0].to, read_instruction("nop -4"));
+
+    let a = analyse(&assemble("jmp +2\nself: jmp self\nnop +0\njmp -7")?);
+    assert!(!a.terminates);
+    assert_eq!(a.unreachable, vec![1]);
+    assert_eq!(
+      a.loops,
+      vec![InfiniteLoop {
+        lines: vec![1],
+        reached_from_start: false
+      }]
+    );
+    assert_eq!(a.out_of_bounds, vec![(3, -4)]);
+    let patched_lines = a.patches.iter().map(|p| p.line).collect::<Vec<_>>();
+    assert_eq!(patched_lines, vec![3]);
+
+    let a = analyse(&assemble("acc +1\njmp +2\nacc -1\nnop +0")?);
+    assert!(a.terminates);
+    assert!(a.loops.is_empty());
+    assert!(a.patches.is_empty());
+
+    let input_all = std::fs::read_to_string(INPUT_FILE)?;
+    let program = read_program(&input_all);
+    let patched_lines = analyse(&program).patches.iter().map(|p| p.line).collect::<Vec<_>>();
+    assert_eq!(patched_lines, vec![repair(&program).unwrap().line]);
+    Ok(())
+  }
+
+  #[test]
+  fn test_dot() {
+    let dot = to_dot(&read_program(TEST_INPUT));
+    assert!(dot.starts_with("digraph program {\n"));
+    assert!(dot.contains("  l4 -> l1 [color=red];\n"));
+    assert!(dot.contains("  l5 [label=\"5: acc -99\", color=grey, fontcolor=grey];\n"));
+    assert!(dot.contains("  l8 -> end;\n"));
+    assert!(dot.contains("  l7 -> l8 [style=dashed, color=green, label=\"nop\"];\n"));
+  }
+
+  #[test]
+  fn test_repair() -> anyhow::Result<()> {
+    let expected = Repair { line: 7, acc: 8 };
+    assert_eq!(repair(&read_program(TEST_INPUT)), Some(expected));
+    assert_eq!(repair_brute_force(TEST_INPUT), expected);
+
+    let input_all = std::fs::read_to_string(INPUT_FILE)?;
+    assert_eq!(repair(&read_program(&input_all)), Some(repair_brute_force(&input_all)));
+
+    // Flipping the nop jumps out of bounds, and flipping the jmp +0 just
+    // leads into another loop.
+    assert_eq!(repair(&assemble("nop -5\njmp +0\njmp -2")?), None);
+    // Already terminates, where flipping the nop to jmp -1 would loop back
+    // to line 0, which reaches the end.
+    assert_eq!(repair(&assemble("acc +0\nnop -1")?), None);
+    assert!(p2("acc +0\nnop -1\n").is_err());
+    Ok(())
+  }
 }
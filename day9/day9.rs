@@ -1,51 +1,326 @@
+#[macro_use]
+extern crate anyhow;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
 static INPUT_FILE: &str = "day9/input.txt";
+const PREAMBLE_LEN: usize = 25;
+
+// Validates XMAS data, where every number after the preamble must be the sum
+// of two different numbers among the `preamble_len` numbers before it.
+//
+// Along with the window of recent numbers, this keeps a multiset of the sums
+// of every pair of different numbers in the window. Checking a number is a
+// single hash lookup, and sliding the window adds and removes the
+// `preamble_len` sums involving the numbers entering and leaving it.
+struct XmasCipher {
+  preamble_len: usize,
+  window: VecDeque<u64>,
+  // Count of each sum of two different numbers in `window`.
+  pair_sums: HashMap<u64, usize>,
+}
+impl XmasCipher {
+  fn new(preamble_len: usize) -> Self {
+    assert!(preamble_len >= 2);
+    XmasCipher {
+      preamble_len,
+      window: VecDeque::with_capacity(preamble_len + 1),
+      pair_sums: HashMap::new(),
+    }
+  }
+
+  // Whether the preamble has been read, so numbers can be validated.
+  fn has_preamble(&self) -> bool {
+    self.window.len() == self.preamble_len
+  }
+
+  fn is_valid(&self, n: u64) -> bool {
+    assert!(self.has_preamble());
+    self.pair_sums.contains_key(&n)
+  }
+
+  // Adds the next number to the stream. Returns false if the number is
+  // invalid, which can only be the case once the preamble has been read.
+  fn push(&mut self, n: u64) -> bool {
+    let valid = !self.has_preamble() || self.is_valid(n);
+
+    for &other in &self.window {
+      if other != n {
+        if let Some(sum) = other.checked_add(n) {
+          *self.pair_sums.entry(sum).or_insert(0) += 1;
+        }
+      }
+    }
+    self.window.push_back(n);
 
-fn find_sum_of_2(list: &Vec<&u64>, search: &u64) -> bool {
-  for i in 0..list.len() {
-    for j in i + 1..list.len() {
-      if list[i] + list[j] == *search {
-        return true;
+    if self.window.len() > self.preamble_len {
+      let old = self.window.pop_front().unwrap();
+      for &other in &self.window {
+        if other != old {
+          if let Some(sum) = other.checked_add(old) {
+            let count = self.pair_sums.get_mut(&sum).unwrap();
+            *count -= 1;
+            if *count == 0 {
+              self.pair_sums.remove(&sum);
+            }
+          }
+        }
       }
     }
+    valid
+  }
+
+  // Finds the first invalid number in `nums`, as (index, value).
+  fn first_invalid(preamble_len: usize, nums: &[u64]) -> Option<(usize, u64)> {
+    let mut cipher = XmasCipher::new(preamble_len);
+    nums.iter().copied().enumerate().find(|&(_, n)| !cipher.push(n))
+  }
+}
+
+// Finds a contiguous range of at least two numbers that sums to `target`.
+//
+// Since the numbers are never negative, the sum of the range between two
+// pointers only grows as the end moves forward and only shrinks as the start
+// moves forward, so a single pass over the prefix sums finds it.
+fn find_weakness_range(nums: &[u64], target: u64) -> Option<std::ops::Range<usize>> {
+  let mut prefix_sums: Vec<u128> = Vec::with_capacity(nums.len() + 1);
+  prefix_sums.push(0);
+  for &n in nums {
+    prefix_sums.push(prefix_sums.last().unwrap() + n as u128);
+  }
+  find_range_with_sum(&prefix_sums, target)
+}
+
+// The same search, given the prefix sums of the numbers, which start with 0.
+fn find_range_with_sum(prefix_sums: &[u128], target: u64) -> Option<std::ops::Range<usize>> {
+  let target = target as u128;
+  let mut begin = 0;
+  for end in 2..prefix_sums.len() {
+    while end - begin > 2 && prefix_sums[end] - prefix_sums[begin] > target {
+      begin += 1;
+    }
+    if prefix_sums[end] - prefix_sums[begin] == target {
+      return Some(begin..end);
+    }
   }
-  return false;
+  None
+}
+
+// The sum of the smallest and largest numbers in `range`.
+fn range_weakness(nums: &[u64], range: std::ops::Range<usize>) -> u64 {
+  let min = nums[range.clone()].iter().min().unwrap();
+  let max = nums[range].iter().max().unwrap();
+  min + max
+}
+
+// The sum of the smallest and largest numbers in the weakness range.
+fn encryption_weakness(nums: &[u64], target: u64) -> Option<u64> {
+  let range = find_weakness_range(nums, target)?;
+  Some(range_weakness(nums, range))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Weakness {
+  // The contiguous range of the stream, by index, which sums to the invalid
+  // number.
+  range: std::ops::Range<usize>,
+  // The sum of the smallest and largest numbers in `range`.
+  value: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct InvalidNumber {
+  index: usize,
+  value: u64,
+  // The weakness among the numbers before the invalid one, when requested.
+  weakness: Option<Weakness>,
 }
 
-fn main() {
+// Reads numbers one line at a time from a reader, producing every invalid
+// number in the stream. Only the window of recent numbers is kept in memory,
+// unless weaknesses are requested, which needs the numbers seen so far.
+struct InvalidNumbers<R: std::io::BufRead> {
+  lines: std::io::Lines<R>,
+  cipher: XmasCipher,
+  // The index of the next number in the stream.
+  index: usize,
+  // Every number read so far, when finding weaknesses.
+  history: Option<Vec<u64>>,
+  // The prefix sums of `history`, extended as each number is read so they
+  // aren't recomputed for every invalid number.
+  prefix_sums: Vec<u128>,
+}
+impl<R: std::io::BufRead> InvalidNumbers<R> {
+  fn new(reader: R, preamble_len: usize, find_weakness: bool) -> Self {
+    InvalidNumbers {
+      lines: reader.lines(),
+      cipher: XmasCipher::new(preamble_len),
+      index: 0,
+      history: if find_weakness { Some(Vec::new()) } else { None },
+      prefix_sums: vec![0],
+    }
+  }
+}
+impl<R: std::io::BufRead> Iterator for InvalidNumbers<R> {
+  type Item = anyhow::Result<InvalidNumber>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    for line in &mut self.lines {
+      let line = match line {
+        Ok(line) => line,
+        Err(e) => return Some(Err(e.into())),
+      };
+      let line = line.trim();
+      if line.is_empty() {
+        continue;
+      }
+      let n: u64 = match line.parse() {
+        Ok(n) => n,
+        Err(e) => return Some(Err(anyhow!("number {}: invalid `{}`: {}", self.index, line, e))),
+      };
+
+      let index = self.index;
+      self.index += 1;
+      let valid = self.cipher.push(n);
+      if let Some(history) = &mut self.history {
+        history.push(n);
+        self.prefix_sums.push(self.prefix_sums.last().unwrap() + n as u128);
+      }
+      if !valid {
+        let prefix_sums = &self.prefix_sums;
+        let weakness = self.history.as_ref().and_then(|history| {
+          let range = find_range_with_sum(&prefix_sums[..=index], n)?;
+          Some(Weakness {
+            value: range_weakness(history, range.clone()),
+            range,
+          })
+        });
+        return Some(Ok(InvalidNumber {
+          index,
+          value: n,
+          weakness,
+        }));
+      }
+    }
+    None
+  }
+}
+
+// Checks the numbers on stdin as they arrive, printing each invalid one.
+fn validate_stdin(preamble_len: usize, find_weakness: bool) -> anyhow::Result<()> {
+  let stdin = std::io::stdin();
+  for invalid in InvalidNumbers::new(stdin.lock(), preamble_len, find_weakness) {
+    let invalid = invalid?;
+    print!("Invalid number {} at index {}", invalid.value, invalid.index);
+    match invalid.weakness {
+      Some(w) => println!(", weakness {} in {}..{}", w.value, w.range.start, w.range.end),
+      None if find_weakness => println!(", no weakness"),
+      None => println!(),
+    }
+  }
+  Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+  // `day9 stream [--weakness] [PREAMBLE_LEN]` validates numbers from stdin.
+  let args = std::env::args().skip(1).collect::<Vec<_>>();
+  if args.first().map(|s| s.as_str()) == Some("stream") {
+    let find_weakness = args.iter().any(|s| s == "--weakness");
+    let preamble_len = match args.iter().skip(1).find(|s| *s != "--weakness") {
+      Some(s) => s.parse()?,
+      None => PREAMBLE_LEN,
+    };
+    return validate_stdin(preamble_len, find_weakness);
+  }
+
   let input_all: String = std::fs::read_to_string(INPUT_FILE).unwrap();
   let nums_all: Vec<u64> = input_all
     .split_terminator("\n")
     .map(|x: &str| x.parse().unwrap())
     .collect();
 
-  let mut preamble: Vec<&u64> = nums_all.iter().take(25).collect();
-  let invalid = {
-    let mut it = nums_all.iter().skip(25);
-    loop {
-      let n = it.next().unwrap();
-      if !find_sum_of_2(&preamble, n) {
-        break n;
-      }
-      preamble.push(n);
-      preamble.remove(0);
-      assert!(preamble.len() == 25);
-    }
-  };
+  let (_, invalid) = XmasCipher::first_invalid(PREAMBLE_LEN, &nums_all).unwrap();
   println!("Part 1 {}", invalid);
+  println!("Part 2 {}", encryption_weakness(&nums_all, invalid).unwrap());
+  Ok(())
+}
 
-  'p2_outer: for begin in 0..nums_all.len() - 2 {
-    for end in begin+2..nums_all.len() {
-      assert!(begin < end);
-      let sum = nums_all[begin..end].iter().fold(0, |sum, i| sum + i);
-      if sum == *invalid {
-        let min = nums_all[begin..end].iter().min().unwrap();
-        let max = nums_all[begin..end].iter().max().unwrap();
-        println!("Part 2 {}", min + max);
-        break 'p2_outer;
-      } else if sum > *invalid {
-        break;  // Go to the next `begin`, the sum is too large already.
-      }
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const TEST_INPUT: [u64; 20] = [
+    35, 20, 15, 25, 47, 40, 62, 55, 65, 95, 102, 117, 150, 182, 127, 219, 299, 277, 309, 576,
+  ];
+
+  #[test]
+  fn test_example() {
+    assert_eq!(XmasCipher::first_invalid(5, &TEST_INPUT), Some((14, 127)));
+    assert_eq!(find_weakness_range(&TEST_INPUT, 127), Some(2..6));
+    assert_eq!(encryption_weakness(&TEST_INPUT, 127), Some(62));
+  }
+
+  #[test]
+  fn test_window() {
+    // Numbers 1 through 25 in any order make every number from 3 to 49 valid.
+    let mut cipher = XmasCipher::new(25);
+    for n in (1..=25).rev() {
+      assert!(cipher.push(n));
     }
+    assert!(cipher.is_valid(26));
+    assert!(cipher.is_valid(49));
+    assert!(!cipher.is_valid(100));
+    assert!(!cipher.is_valid(50));
+    // The numbers must be different.
+    let mut cipher = XmasCipher::new(2);
+    cipher.push(7);
+    cipher.push(7);
+    assert!(!cipher.is_valid(14));
+    // Numbers drop out of the window.
+    let mut cipher = XmasCipher::new(2);
+    cipher.push(1);
+    cipher.push(2);
+    assert!(cipher.push(3));
+    assert!(!cipher.push(4));
+    assert!(!cipher.push(5));
+    assert!(cipher.push(9));
   }
 
+  #[test]
+  fn test_stream() -> anyhow::Result<()> {
+    let input = TEST_INPUT.iter().map(|n| format!("{}\n", n)).collect::<String>();
+    let invalid = InvalidNumbers::new(input.as_bytes(), 5, false).collect::<anyhow::Result<Vec<_>>>()?;
+    let positions = invalid.iter().map(|i| (i.index, i.value)).collect::<Vec<_>>();
+    assert_eq!(positions, vec![(14, 127)]);
+
+    // Both numbers after the preamble are invalid, and the second is the sum
+    // of the first two.
+    let input = "1\n2\n\n100\n3\n";
+    let invalid = InvalidNumbers::new(input.as_bytes(), 2, true).collect::<anyhow::Result<Vec<_>>>()?;
+    let expected = vec![
+      InvalidNumber {
+        index: 2,
+        value: 100,
+        weakness: None,
+      },
+      InvalidNumber {
+        index: 3,
+        value: 3,
+        weakness: Some(Weakness { range: 0..2, value: 3 }),
+      },
+    ];
+    assert_eq!(invalid, expected);
+
+    let mut it = InvalidNumbers::new("1\n2\nthree\n".as_bytes(), 2, false);
+    assert!(it.next().unwrap().is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_no_weakness() {
+    assert_eq!(find_weakness_range(&[1, 2, 3], 7), None);
+    // A single number is not a range.
+    assert_eq!(find_weakness_range(&[1, 5, 1], 5), None);
+    assert_eq!(find_weakness_range(&[1, 5, 1], 6), Some(0..2));
+  }
 }